@@ -0,0 +1,138 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// A `[[package]]` entry of Cargo.lock
+struct Package {
+    name: String,
+    version: String,
+    dependencies: Vec<String>,
+}
+
+// Only the fields needed here, Cargo.lock always has one per line
+fn parse_lock(lock: &str) -> Vec<Package> {
+    let mut packages = Vec::new();
+    let mut in_dependencies = false;
+
+    for line in lock.lines().map(str::trim) {
+        let value = |prefix: &str| {
+            line.strip_prefix(prefix)
+                .map(|value| value.trim_matches(|c| c == '"' || c == ',').to_string())
+        };
+
+        if line == "[[package]]" {
+            packages.push(Package {
+                name: String::new(),
+                version: String::new(),
+                dependencies: Vec::new(),
+            });
+        } else if let Some(package) = packages.last_mut() {
+            if in_dependencies {
+                match value("") {
+                    _ if line == "]" => in_dependencies = false,
+                    Some(dependency) => package.dependencies.push(dependency),
+                    None => {}
+                }
+            } else if let Some(name) = value("name = ") {
+                package.name = name;
+            } else if let Some(version) = value("version = ") {
+                package.version = version;
+            } else if line == "dependencies = [" {
+                in_dependencies = true;
+            }
+        }
+    }
+
+    packages
+}
+
+// Dependencies are listed as `name`, or `name version` when the lock has
+// several versions of it
+fn find<'a>(packages: &'a [Package], dependency: &str) -> Option<&'a Package> {
+    let mut parts = dependency.split(' ');
+    let (name, version) = (parts.next()?, parts.next());
+
+    packages
+        .iter()
+        .find(|package| package.name == name && version.is_none_or(|v| package.version == v))
+}
+
+// `name version` of the crates lenz_core depends on, the ones its types come
+// from. Their own dependencies vary with the features each workspace enables.
+fn dependency_versions(packages: &[Package]) -> BTreeSet<String> {
+    find(packages, env!("CARGO_PKG_NAME"))
+        .map(|core| {
+            core.dependencies
+                .iter()
+                .filter_map(|dependency| find(packages, dependency))
+                .map(|package| format!("{} {}", package.name, package.version))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The lock of the workspace being built, which is not the one of this crate
+// when plugins depend on it by path. The target dir is usually next to it,
+// `LENZ_CARGO_LOCK` points to it otherwise.
+fn workspace_lock() -> Option<PathBuf> {
+    if let Ok(lock) = std::env::var("LENZ_CARGO_LOCK") {
+        return Some(PathBuf::from(lock));
+    }
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").ok()?);
+
+    out_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|lock| lock.is_file())
+}
+
+// Without the versions every build would get the same hash, and plugins
+// built against other dependencies would be loaded
+fn dependencies_hash(path: &Path) -> Result<String, String> {
+    let lock = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let versions = dependency_versions(&parse_lock(&lock));
+
+    if versions.is_empty() {
+        return Err(format!(
+            "{} doesn't list the dependencies of {}",
+            path.display(),
+            env!("CARGO_PKG_NAME")
+        ));
+    }
+
+    let mut hasher = DefaultHasher::new();
+    versions.hash(&mut hasher);
+
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+// Plugins share Rust types with the agent, which are only compatible between
+// builds of the same compiler and dependencies, see
+// `extensions::plugin::RUSTC_VERSION` and `extensions::plugin::DEPENDENCIES`
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .unwrap_or_default();
+
+    let Some(lock) = workspace_lock() else {
+        panic!("Cargo.lock not found above the target dir, set LENZ_CARGO_LOCK to its path");
+    };
+
+    let hash = dependencies_hash(&lock).unwrap_or_else(|e| panic!("{}", e));
+
+    println!("cargo:rustc-env=LENZ_RUSTC_VERSION={}", version.trim());
+    println!("cargo:rustc-env=LENZ_DEPENDENCIES_HASH={}", hash);
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-env-changed=LENZ_CARGO_LOCK");
+    println!("cargo:rerun-if-changed={}", lock.display());
+}
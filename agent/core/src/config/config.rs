@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use super::ServerConfig;
+
 #[derive(Debug)]
 pub struct AgentConfig {
     pub install_dir: PathBuf,
@@ -8,14 +10,21 @@ pub struct AgentConfig {
     pub www_dir: PathBuf,
     pub vendor_dir: PathBuf,
     pub app_data_dir: PathBuf,
+    pub temp_dir: PathBuf,
+    pub ipc_socket: PathBuf,
     pub built_in_extensions_dir: PathBuf,
     pub user_extensions_dir: PathBuf,
     pub settings_file: PathBuf,
     pub extensions_search_paths: Vec<PathBuf>,
+    pub log_invokes: bool,
+    pub dev_mode: bool,
+    pub server: ServerConfig,
 }
 
 impl AgentConfig {
     pub fn load() -> Self {
+        let settings_file = crate::config::util::settings();
+
         Self {
             install_dir: crate::config::util::install_dir(),
             resources_dir: crate::config::util::resources_dir(),
@@ -23,10 +32,15 @@ impl AgentConfig {
             vendor_dir: crate::config::util::vendor_dir(),
             www_dir: crate::config::util::www_dir(),
             app_data_dir: crate::config::util::app_data(),
+            temp_dir: crate::config::util::temp_dir(),
+            ipc_socket: crate::config::util::ipc_socket(),
             built_in_extensions_dir: crate::config::util::built_in_extensions(),
             user_extensions_dir: crate::config::util::user_extensions(),
-            settings_file: crate::config::util::settings(),
+            server: ServerConfig::load(&settings_file),
+            settings_file,
             extensions_search_paths: crate::config::util::extensions_search_paths(),
+            log_invokes: crate::config::util::log_invokes(),
+            dev_mode: crate::config::util::dev_mode(),
         }
     }
 }
\ No newline at end of file
@@ -1,2 +1,2 @@
-pub const ADDR: &str = "localhost:5369";
-pub const BASE_URL: &str = "http://localhost:5369";
\ No newline at end of file
+pub const DEFAULT_HOST: &str = "localhost";
+pub const DEFAULT_PORT: u16 = 5369;
@@ -0,0 +1,72 @@
+use std::{collections::BTreeSet, path::Path};
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct ExtensionsSettings {
+    #[serde(default)]
+    disabled: BTreeSet<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct Settings {
+    #[serde(default)]
+    extensions: ExtensionsSettings,
+}
+
+// Extensions are enabled unless listed under `extensions.disabled` in
+// settings.json, so newly installed ones start enabled
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionsConfig {
+    pub disabled: BTreeSet<String>,
+}
+
+impl ExtensionsConfig {
+    pub fn load(settings_file: &Path) -> Self {
+        let Ok(content) = std::fs::read_to_string(settings_file) else {
+            return Self::default();
+        };
+
+        match serde_json::from_str::<Settings>(&content) {
+            Ok(settings) => Self {
+                disabled: settings.extensions.disabled,
+            },
+            Err(e) => {
+                eprintln!("Invalid settings file {}: {}", settings_file.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn is_enabled(&self, id: &str) -> bool {
+        !self.disabled.contains(id)
+    }
+
+    // Only touches `extensions.disabled`, the rest of the file is kept as is
+    pub fn save(&self, settings_file: &Path) -> std::io::Result<()> {
+        let mut settings = match std::fs::read_to_string(settings_file) {
+            Ok(content) => serde_json::from_str(&content).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({}),
+            Err(e) => return Err(e),
+        };
+
+        if !settings.is_object() {
+            return Err(std::io::Error::other("settings.json is not an object"));
+        }
+
+        if !settings["extensions"].is_object() {
+            settings["extensions"] = serde_json::json!({});
+        }
+
+        settings["extensions"]["disabled"] = serde_json::json!(self.disabled);
+
+        if let Some(parent) = settings_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Written next to it and renamed, a crash never leaves it half written
+        let temp = settings_file.with_extension("json.tmp");
+        let content = serde_json::to_string_pretty(&settings).map_err(std::io::Error::other)?;
+
+        std::fs::write(&temp, content)?;
+        std::fs::rename(&temp, settings_file)
+    }
+}
@@ -1,5 +1,10 @@
 pub mod util;
+#[allow(clippy::module_inception)]
 mod config;
+mod extensions;
+mod server;
 
 pub mod consts;
-pub use config::AgentConfig;
\ No newline at end of file
+pub use config::AgentConfig;
+pub use extensions::ExtensionsConfig;
+pub use server::ServerConfig;
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use super::{
+    consts::{DEFAULT_HOST, DEFAULT_PORT},
+    util,
+};
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerSettings {
+    host: Option<String>,
+    port: Option<u16>,
+    port_fallback: Option<bool>,
+    allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct Settings {
+    #[serde(default)]
+    server: ServerSettings,
+}
+
+impl ServerSettings {
+    fn read(settings_file: &Path) -> Self {
+        let Ok(content) = std::fs::read_to_string(settings_file) else {
+            return Self::default();
+        };
+
+        match serde_json::from_str::<Settings>(&content) {
+            Ok(settings) => settings.server,
+            Err(e) => {
+                eprintln!("Invalid settings file {}: {}", settings_file.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // Bind to any free port when `port` is already taken
+    pub port_fallback: bool,
+    // Origins besides the agent itself allowed to call it from a browser
+    pub allowed_origins: Vec<String>,
+}
+
+impl ServerConfig {
+    // CLI flags take precedence over env vars, which take precedence over settings.json
+    pub fn load(settings_file: &Path) -> Self {
+        let settings = ServerSettings::read(settings_file);
+
+        let port = util::cli_arg("port")
+            .or_else(|| std::env::var("LENZ_PORT").ok())
+            .and_then(|port| {
+                port.parse::<u16>()
+                    .inspect_err(|e| eprintln!("Invalid port {}: {}", port, e))
+                    .ok()
+            })
+            .or(settings.port)
+            .unwrap_or(DEFAULT_PORT);
+
+        Self {
+            host: util::cli_arg("host")
+                .or_else(|| std::env::var("LENZ_HOST").ok())
+                .or(settings.host)
+                .unwrap_or_else(|| DEFAULT_HOST.to_string()),
+            port,
+            port_fallback: util::cli_flag("port-fallback")
+                || util::env_flag("LENZ_PORT_FALLBACK")
+                || settings.port_fallback.unwrap_or(false),
+            allowed_origins: util::cli_arg("allow-origin")
+                .or_else(|| std::env::var("LENZ_ALLOWED_ORIGINS").ok())
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                        .filter(|origin| !origin.is_empty())
+                        .collect()
+                })
+                .or(settings.allowed_origins)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr())
+    }
+}
@@ -1,22 +1,67 @@
 use std::path::PathBuf;
 
+// CLI flags that take a value, so it isn't mistaken for a positional argument
+const CLI_VALUE_FLAGS: [&str; 4] = ["--host", "--port", "--allow-origin", "--project"];
+
+// Value of `--name value` or `--name=value`
+pub fn cli_arg(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+
+        if let Some(value) = arg.strip_prefix(&flag).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+pub fn cli_flag(name: &str) -> bool {
+    let flag = format!("--{name}");
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
+
+pub fn cli_positional_args() -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if CLI_VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            positional.push(arg);
+        }
+    }
+
+    positional
+}
+
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
 pub fn install_dir() -> PathBuf {
-    if let Some(cargo_manifest_dir) = std::env::var("CARGO_MANIFEST_DIR").ok() {
+    if let Ok(cargo_manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
         PathBuf::from(cargo_manifest_dir)
             .join("../../dist/plain")
             .canonicalize()
             .expect("Failed to get canonical path")
     } else {
-        let x = std::env::current_exe()
+        std::env::current_exe()
             .expect("Failed to get current exe")
             .parent()
             .expect("Failed to get parent")
             .join("../")
             .canonicalize()
             .expect("Failed to get canonical path")
-            .to_path_buf();
-
-        x
+            .to_path_buf()
     }
 }
 
@@ -27,19 +72,15 @@ pub fn resources_dir() -> PathBuf {
 }
 
 pub fn esm_dir() -> PathBuf {
-    let dir = std::env::var("LENZ_ESM_DIR")
+    std::env::var("LENZ_ESM_DIR")
         .map(PathBuf::from)
-        .unwrap_or_else(|_| resources_dir().join("esm"));
-
-    dir
+        .unwrap_or_else(|_| resources_dir().join("esm"))
 }
 
 pub fn vendor_dir() -> PathBuf {
-    let dir = std::env::var("LENZ_ESM_DIR")
+    std::env::var("LENZ_ESM_DIR")
         .map(PathBuf::from)
-        .unwrap_or_else(|_| resources_dir().join("vendor"));
-
-    dir
+        .unwrap_or_else(|_| resources_dir().join("vendor"))
 }
 
 pub fn www_dir() -> PathBuf {
@@ -54,6 +95,33 @@ pub fn app_data() -> PathBuf {
         .unwrap_or_else(|_| dirs::home_dir().unwrap_or_else(install_dir).join(".lenz"))
 }
 
+// Only the current user can reach its runtime dir or the app data, unlike
+// the system temporary folder that all users share
+pub fn temp_dir() -> PathBuf {
+    std::env::var("LENZ_TEMP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            dirs::runtime_dir()
+                .map(|dir| dir.join("lenz"))
+                .unwrap_or_else(|| app_data().join("tmp"))
+        })
+}
+
+pub fn log_invokes() -> bool {
+    env_flag("LENZ_LOG_INVOKES")
+}
+
+// Reloads extensions when their files change, for extension authors
+pub fn dev_mode() -> bool {
+    env_flag("LENZ_DEV")
+}
+
+pub fn ipc_socket() -> PathBuf {
+    std::env::var("LENZ_IPC_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| app_data().join("agent.sock"))
+}
+
 pub fn built_in_extensions() -> PathBuf {
     std::env::var("LENZ_BUILT_IN_EXTENSIONS_PATH")
         .map(PathBuf::from)
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock, Weak,
+    },
+};
+
+use tokio::sync::broadcast;
+
+use super::Event;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+pub const ANY_EVENT: &str = "*";
+
+// Sent with the `connection` id when a channel connection closes, see
+// `InvokeRequest::connection`
+pub const DISCONNECTED_EVENT: &str = "app.disconnected";
+
+pub type EventListener = dyn Fn(&Event) + 'static + Send + Sync;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Subscription {
+    event: String,
+    id: u64,
+}
+
+// Listeners added through a leased bus keep the lease while they run
+type Listeners = HashMap<String, Vec<(u64, Arc<EventListener>, Option<Weak<()>>)>>;
+
+// Listeners run synchronously on the publisher's thread, async consumers
+// (like the WebSocket channel) should use `subscribe` instead.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    listeners: Arc<RwLock<Listeners>>,
+    next_id: Arc<AtomicU64>,
+    lease: Option<Weak<()>>,
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("listeners", &self.listeners.read().unwrap().keys())
+            .finish()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            sender,
+            listeners: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+            lease: None,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The same bus, with the listeners added through it holding `lease`
+    // while they run, see `emit`. Listeners whose lease is gone are skipped.
+    pub fn with_lease(&self, lease: Weak<()>) -> Self {
+        Self {
+            lease: Some(lease),
+            ..self.clone()
+        }
+    }
+
+    pub fn emit<T: serde::Serialize>(&self, event: &str, data: T) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to serialize event {}: {}", event, e);
+                return;
+            }
+        };
+
+        let event = Event::new(event, data);
+
+        let listeners = {
+            let listeners = self.listeners.read().unwrap();
+
+            [event.event.as_str(), ANY_EVENT]
+                .iter()
+                .filter_map(|name| listeners.get(*name))
+                .flatten()
+                .filter_map(|(_, listener, lease)| match lease {
+                    Some(lease) => Some((listener.clone(), Some(lease.upgrade()?))),
+                    None => Some((listener.clone(), None)),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The listener may be the last reference to code from a plugin
+        // library, it is dropped before the lease
+        for (listener, lease) in listeners {
+            listener(&event);
+            drop(listener);
+            drop(lease);
+        }
+
+        // Having no async subscribers is not an error
+        self.sender.send(event).ok();
+    }
+
+    pub fn on<T, F>(&self, event: &str, listener: F) -> Subscription
+    where
+        T: serde::de::DeserializeOwned,
+        F: Fn(T) + 'static + Send + Sync,
+    {
+        self.on_event(event, move |event: &Event| match event.data_as::<T>() {
+            Ok(data) => listener(data),
+            Err(e) => eprintln!("Failed to deserialize event {}: {}", event.event, e),
+        })
+    }
+
+    pub fn on_event<F>(&self, event: &str, listener: F) -> Subscription
+    where
+        F: Fn(&Event) + 'static + Send + Sync,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.listeners
+            .write()
+            .unwrap()
+            .entry(event.to_string())
+            .or_default()
+            .push((id, Arc::new(listener), self.lease.clone()));
+
+        Subscription {
+            event: event.to_string(),
+            id,
+        }
+    }
+
+    pub fn off(&self, subscription: &Subscription) {
+        let mut listeners = self.listeners.write().unwrap();
+
+        if let Some(entries) = listeners.get_mut(&subscription.event) {
+            entries.retain(|(id, _, _)| *id != subscription.id);
+
+            if entries.is_empty() {
+                listeners.remove(&subscription.event);
+            }
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn leased_listeners_hold_the_lease_while_running() {
+        let lease = Arc::new(());
+        let events = EventBus::new();
+        let leased = events.with_lease(Arc::downgrade(&lease));
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let seen = count.clone();
+        let weak = Arc::downgrade(&lease);
+        leased.on("test", move |_: serde_json::Value| {
+            seen.store(weak.strong_count(), Ordering::SeqCst);
+        });
+
+        events.emit("test", ());
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert_eq!(Arc::strong_count(&lease), 1);
+    }
+
+    #[test]
+    fn skips_listeners_whose_lease_is_gone() {
+        let lease = Arc::new(());
+        let events = EventBus::new();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let seen = count.clone();
+        events
+            .with_lease(Arc::downgrade(&lease))
+            .on("test", move |_: serde_json::Value| {
+                seen.fetch_add(1, Ordering::SeqCst);
+            });
+
+        drop(lease);
+        events.emit("test", ());
+
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+}
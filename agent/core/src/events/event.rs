@@ -0,0 +1,18 @@
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Event {
+    pub event: String,
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    pub fn new(event: &str, data: serde_json::Value) -> Self {
+        Self {
+            event: event.to_string(),
+            data,
+        }
+    }
+
+    pub fn data_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(&self.data)
+    }
+}
@@ -0,0 +1,5 @@
+mod bus;
+mod event;
+
+pub use bus::{EventBus, EventListener, Subscription, ANY_EVENT, DISCONNECTED_EVENT};
+pub use event::Event;
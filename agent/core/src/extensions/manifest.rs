@@ -1,4 +1,4 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, path::Path};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum ExtensionIcon {
@@ -108,6 +108,7 @@ pub enum ExtensionError {
   FailedToLoadManifestFile(String),
   FailedToParseManifestFile(String),
   MainScriptNotFound,
+  FailedToLoadDynlib(String),
 }
 
 impl std::fmt::Display for ExtensionError {
@@ -125,12 +126,15 @@ impl std::fmt::Display for ExtensionError {
       ExtensionError::MainScriptNotFound => {
         write!(f, "Main script not found")
       }
+      ExtensionError::FailedToLoadDynlib(err) => {
+        write!(f, "Failed to load dynlib > {}", err)
+      }
     }
   }
 }
 
 impl ExtensionManifest {
-  pub fn from_path(path: &PathBuf) -> Result<Self, ExtensionError> {
+  pub fn from_path(path: &Path) -> Result<Self, ExtensionError> {
     let manifest_path = if path.is_dir() {
       let manifest_path = path.join("manifest.json");
 
@@ -156,12 +160,12 @@ impl ExtensionManifest {
     };
 
     if let Some(main_script_path) = manifest.main.clone() {
-      let main_script_path = path.join(&main_script_path.trim_start_matches("..").trim_end_matches("/"));
+      let main_script_path = path.join(main_script_path.trim_start_matches("..").trim_end_matches("/"));
       if !main_script_path.is_file() {
         return Err(ExtensionError::MainScriptNotFound);
       }
     }
 
-    return Ok(manifest);
+    Ok(manifest)
   }
 }
@@ -1,13 +1,31 @@
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void},
+    fmt::Debug,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+    sync::Arc,
+};
 
-use crate::invoke::InvokeHandler;
+use crate::{
+    events::{EventBus, Subscription},
+    invoke::{InvokeHandler, InvokeHandlerInfo},
+};
 
 use super::manifest::ExtensionManifest;
 
+pub type OpenHandler = dyn Fn(&Path) + Send + Sync;
+
 pub struct LenzPluginContext {
     pub manifest: ExtensionManifest,
     pub invoke_handlers: HashMap<String, Arc<InvokeHandler>>,
+    pub invoke_handlers_info: HashMap<String, InvokeHandlerInfo>,
     pub import_map: HashMap<String, String>,
+    pub events: EventBus,
+    pub subscriptions: Vec<Subscription>,
+    // Called by the agent with files the user opened it with, never reachable
+    // from clients like events are
+    pub open_handlers: Vec<Arc<OpenHandler>>,
 }
 
 impl Debug for LenzPluginContext {
@@ -16,21 +34,185 @@ impl Debug for LenzPluginContext {
             .field("manifest", &self.manifest)
             .field("invoke_handlers", &self.invoke_handlers.keys())
             .field("import_map", &self.import_map.keys())
+            .field("subscriptions", &self.subscriptions)
+            .field("open_handlers", &self.open_handlers.len())
             .finish()
     }
 }
 
 impl LenzPluginContext {
-    pub fn new(manifest: ExtensionManifest) -> Self {
+    pub fn new(manifest: ExtensionManifest, events: EventBus) -> Self {
         Self {
             manifest,
             invoke_handlers: HashMap::new(),
+            invoke_handlers_info: HashMap::new(),
             import_map: HashMap::new(),
+            events,
+            subscriptions: Vec::new(),
+            open_handlers: Vec::new(),
         }
     }
+
+    pub fn describe(&mut self, info: InvokeHandlerInfo) {
+        let info = info.with_extension(&self.manifest.id);
+        self.invoke_handlers_info.insert(info.command.clone(), info);
+    }
+
+    pub fn emit<T: serde::Serialize>(&self, event: &str, data: T) {
+        self.events.emit(event, data);
+    }
+
+    pub fn on<T, F>(&mut self, event: &str, listener: F)
+    where
+        T: serde::de::DeserializeOwned,
+        F: Fn(T) + 'static + Send + Sync,
+    {
+        let subscription = self.events.on(event, listener);
+        self.subscriptions.push(subscription);
+    }
+
+    pub fn on_open<F>(&mut self, handler: F)
+    where
+        F: Fn(&Path) + 'static + Send + Sync,
+    {
+        self.open_handlers.push(Arc::new(handler));
+    }
 }
 
 pub trait LenzPlugin: Send + Sync {
     fn activate(&mut self, context: &mut LenzPluginContext);
     fn destroy(&self, context: &mut LenzPluginContext);
 }
+
+// Bumped whenever `LenzPluginDeclaration` or `LenzPluginHandle` change
+pub const ABI_VERSION: u32 = 2;
+
+// The context still hands Rust types (maps, `Arc`s, closures) across the
+// library boundary, their layout is only the same for the same compiler and
+// core version
+pub const CORE_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+pub const RUSTC_VERSION: &str = concat!(env!("LENZ_RUSTC_VERSION"), "\0");
+
+// The same core version can still be built against other versions of tokio,
+// serde_json, bytes or futures-util, each workspace has its own Cargo.lock.
+// Hash of the versions of the crates lenz_core depends on, see `build.rs`.
+pub const DEPENDENCIES: &str = concat!(env!("LENZ_DEPENDENCIES_HASH"), "\0");
+
+// Exported by plugins as `LENZ_PLUGIN`, see `declare_plugin!`. The host reads
+// `abi_version` before anything else, so it must stay the first field.
+#[repr(C)]
+pub struct LenzPluginDeclaration {
+    pub abi_version: u32,
+    pub core_version: *const c_char,
+    pub rustc_version: *const c_char,
+    pub dependencies: *const c_char,
+    pub create: unsafe extern "C" fn() -> LenzPluginHandle,
+}
+
+// Only points to static strings and a function
+unsafe impl Sync for LenzPluginDeclaration {}
+
+impl LenzPluginDeclaration {
+    pub const fn new(create: unsafe extern "C" fn() -> LenzPluginHandle) -> Self {
+        Self {
+            abi_version: ABI_VERSION,
+            core_version: CORE_VERSION.as_ptr() as *const c_char,
+            rustc_version: RUSTC_VERSION.as_ptr() as *const c_char,
+            dependencies: DEPENDENCIES.as_ptr() as *const c_char,
+            create,
+        }
+    }
+}
+
+// A plugin instance along with the functions to use it, all compiled into the
+// plugin. The host never goes through a Rust trait object vtable.
+#[repr(C)]
+pub struct LenzPluginHandle {
+    instance: *mut c_void,
+    activate: unsafe extern "C" fn(*mut c_void, *mut LenzPluginContext) -> bool,
+    destroy: unsafe extern "C" fn(*mut c_void, *mut LenzPluginContext) -> bool,
+    drop: unsafe extern "C" fn(*mut c_void),
+}
+
+// `LenzPlugin` requires both
+unsafe impl Send for LenzPluginHandle {}
+unsafe impl Sync for LenzPluginHandle {}
+
+// Panics must not unwind into the host, they are reported as `false` instead.
+// Only with `panic = "unwind"`, like the agent and plugins are built with: a
+// plugin built with `panic = "abort"` takes the whole agent down on its first
+// panic, `catch_unwind` can't stop that.
+unsafe extern "C" fn activate<P: LenzPlugin>(
+    instance: *mut c_void,
+    context: *mut LenzPluginContext,
+) -> bool {
+    let (plugin, context) = (&mut *(instance as *mut P), &mut *context);
+    catch_unwind(AssertUnwindSafe(|| plugin.activate(context))).is_ok()
+}
+
+unsafe extern "C" fn destroy<P: LenzPlugin>(
+    instance: *mut c_void,
+    context: *mut LenzPluginContext,
+) -> bool {
+    let (plugin, context) = (&*(instance as *mut P), &mut *context);
+    catch_unwind(AssertUnwindSafe(|| plugin.destroy(context))).is_ok()
+}
+
+unsafe extern "C" fn drop<P: LenzPlugin>(instance: *mut c_void) {
+    if !instance.is_null() {
+        let plugin = Box::from_raw(instance as *mut P);
+        catch_unwind(AssertUnwindSafe(|| std::mem::drop(plugin))).ok();
+    }
+}
+
+impl LenzPluginHandle {
+    // Runs `create` inside the plugin, a panic leaves the handle empty
+    pub fn new<P: LenzPlugin, F: FnOnce() -> P>(create: F) -> Self {
+        let instance = catch_unwind(AssertUnwindSafe(|| Box::into_raw(Box::new(create()))))
+            .map(|plugin| plugin as *mut c_void)
+            .unwrap_or(std::ptr::null_mut());
+
+        Self {
+            instance,
+            activate: activate::<P>,
+            destroy: destroy::<P>,
+            drop: drop::<P>,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instance.is_null()
+    }
+
+    // False when the plugin panicked
+    pub fn activate(&mut self, context: &mut LenzPluginContext) -> bool {
+        !self.is_empty() && unsafe { (self.activate)(self.instance, context) }
+    }
+
+    pub fn destroy(&mut self, context: &mut LenzPluginContext) -> bool {
+        !self.is_empty() && unsafe { (self.destroy)(self.instance, context) }
+    }
+}
+
+impl Drop for LenzPluginHandle {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.instance) }
+    }
+}
+
+// Exports the `LENZ_PLUGIN` declaration the agent loads plugins from, with
+// `create` returning the plugin
+#[macro_export]
+macro_rules! declare_plugin {
+    ($create:expr) => {
+        #[no_mangle]
+        pub static LENZ_PLUGIN: $crate::extensions::plugin::LenzPluginDeclaration =
+            $crate::extensions::plugin::LenzPluginDeclaration::new({
+                unsafe extern "C" fn create() -> $crate::extensions::plugin::LenzPluginHandle {
+                    $crate::extensions::plugin::LenzPluginHandle::new($create)
+                }
+
+                create
+            });
+    };
+}
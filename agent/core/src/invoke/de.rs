@@ -0,0 +1,614 @@
+use serde::de::{
+    value::{BorrowedStrDeserializer, SeqDeserializer},
+    DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+};
+
+use super::{
+    error::InvokeError,
+    form::{Form, FormValue},
+};
+
+#[derive(Debug)]
+pub struct FormError {
+    pub message: String,
+    pub argument: Option<String>,
+}
+
+impl FormError {
+    fn for_argument(mut self, argument: &str) -> Self {
+        if self.argument.is_none() {
+            self.message = format!("Invalid `{}` argument: {}", argument, self.message);
+            self.argument = Some(argument.to_string());
+        }
+
+        self
+    }
+}
+
+impl std::fmt::Display for FormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl serde::de::Error for FormError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Self {
+            message: msg.to_string(),
+            argument: None,
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self {
+            message: format!("Missing `{}` argument", field),
+            argument: Some(field.to_string()),
+        }
+    }
+}
+
+impl From<FormError> for InvokeError {
+    fn from(error: FormError) -> Self {
+        let error_details = error
+            .argument
+            .as_ref()
+            .map(|argument| serde_json::json!({ "argument": argument }));
+
+        let invoke_error = InvokeError::invalid_argument(error.message);
+
+        match error_details {
+            Some(details) => invoke_error.with_details(details),
+            None => invoke_error,
+        }
+    }
+}
+
+fn json_error(error: serde_json::Error) -> FormError {
+    serde::de::Error::custom(error)
+}
+
+pub struct FormDeserializer<'de> {
+    form: &'de Form,
+}
+
+impl<'de> FormDeserializer<'de> {
+    pub fn new(form: &'de Form) -> Self {
+        Self { form }
+    }
+}
+
+impl<'de> Deserializer<'de> for FormDeserializer<'de> {
+    type Error = FormError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(FormMapAccess {
+            entries: Box::new(self.form.entries()),
+            current: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct FormMapAccess<'de> {
+    entries: Box<dyn Iterator<Item = (&'de String, &'de Vec<FormValue>)> + 'de>,
+    current: Option<(&'de str, &'de [FormValue])>,
+}
+
+impl<'de> MapAccess<'de> for FormMapAccess<'de> {
+    type Error = FormError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some((key, values)) => {
+                self.current = Some((key.as_str(), values.as_slice()));
+                seed.deserialize(BorrowedStrDeserializer::new(key.as_str()))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, values) = self
+            .current
+            .take()
+            .ok_or_else(|| serde::de::Error::custom("value requested before key"))?;
+
+        seed.deserialize(EntriesDeserializer { values })
+            .map_err(|e| e.for_argument(key))
+    }
+}
+
+// All values sent with the same name. Sequences map to every entry while
+// scalars use the first one, like `Form::get_entry`. A single binary entry is
+// a sequence of its bytes instead.
+struct EntriesDeserializer<'de> {
+    values: &'de [FormValue],
+}
+
+impl<'de> EntriesDeserializer<'de> {
+    fn first(&self) -> Result<ValueDeserializer<'de>, FormError> {
+        self.values
+            .first()
+            .map(|value| ValueDeserializer { value })
+            .ok_or_else(|| serde::de::Error::custom("no value"))
+    }
+}
+
+macro_rules! forward_to_first {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.first()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for EntriesDeserializer<'de> {
+    type Error = FormError;
+
+    forward_to_first! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_unit deserialize_map deserialize_identifier
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.values.is_empty() {
+            visitor.visit_none()
+        } else if self.values.len() == 1 {
+            self.first()?.deserialize_option(visitor)
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if let [value @ (FormValue::Bytes(_) | FormValue::File(_))] = self.values {
+            return ValueDeserializer { value }.deserialize_seq(visitor);
+        }
+
+        let values = self.values.iter().map(|value| ValueDeserializer { value });
+
+        visitor.visit_seq(SeqDeserializer::new(values))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.values.len() == 1 {
+            self.first()?.deserialize_tuple(len, visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.values.len() == 1 {
+            self.first()?.deserialize_tuple_struct(name, len, visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, FormError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+struct ValueDeserializer<'de> {
+    value: &'de FormValue,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn text(&self) -> Result<&'de str, FormError> {
+        match self.value {
+            FormValue::Text(text) => Ok(text.as_str()),
+            FormValue::Bytes(bytes) => std::str::from_utf8(bytes)
+                .map_err(|_| serde::de::Error::custom("expected text, found binary data")),
+            FormValue::File(file) => std::str::from_utf8(&file.data)
+                .map_err(|_| serde::de::Error::custom("expected text, found binary file")),
+            FormValue::Spooled(_) => Err(serde::de::Error::custom(
+                "expected text, found a file upload",
+            )),
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, FormError> {
+        let text = self.text()?;
+
+        text.trim()
+            .parse()
+            .map_err(|_| serde::de::Error::custom(format!("expected {}, found {:?}", expected, text)))
+    }
+
+    fn json(&self) -> Result<serde_json::Value, FormError> {
+        serde_json::from_str(self.text()?).map_err(json_error)
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident : $ty:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+macro_rules! deserialize_json {
+    ($($method:ident $(, $arg:ident : $ty:ty)*);*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+                self.json()?.$method($($arg,)* visitor).map_err(json_error)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = FormError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            FormValue::Text(text) => visitor.visit_borrowed_str(text),
+            FormValue::Bytes(bytes) => visitor.visit_borrowed_bytes(bytes),
+            FormValue::File(file) => visitor.visit_borrowed_bytes(&file.data),
+            FormValue::Spooled(_) => Err(serde::de::Error::custom(
+                "file uploads must be read from the form",
+            )),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.text()?.trim() {
+            "true" | "1" | "on" | "yes" => visitor.visit_bool(true),
+            "false" | "0" | "off" | "no" | "" => visitor.visit_bool(false),
+            text => Err(serde::de::Error::custom(format!(
+                "expected bool, found {:?}",
+                text
+            ))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_u128 => visit_u128: u128,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.text()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            FormValue::Text(text) => visitor.visit_borrowed_bytes(text.as_bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            FormValue::Text(text) if text.is_empty() || text == "null" => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            FormValue::Text(_) => self.json()?.deserialize_seq(visitor).map_err(json_error),
+            FormValue::Bytes(bytes) => {
+                visitor.visit_seq(SeqDeserializer::new(bytes.iter().copied()))
+            }
+            FormValue::File(file) => {
+                visitor.visit_seq(SeqDeserializer::new(file.data.iter().copied()))
+            }
+            FormValue::Spooled(_) => self.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_json! {
+        deserialize_map;
+        deserialize_tuple, len: usize;
+        deserialize_tuple_struct, name: &'static str, len: usize;
+        deserialize_struct, name: &'static str, fields: &'static [&'static str]
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let text = self.text()?;
+
+        if text.starts_with('{') || text.starts_with('"') {
+            self.json()?
+                .deserialize_enum(name, variants, visitor)
+                .map_err(json_error)
+        } else {
+            visitor.visit_enum(text.into_deserializer())
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::invoke::InvokeErrorCode;
+
+    fn form(entries: &[(&str, &str)]) -> Form {
+        let mut form = Form::new();
+
+        for (key, value) in entries {
+            form.append(key.to_string(), FormValue::Text(value.to_string()));
+        }
+
+        form
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Read,
+        Write,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Args {
+        path: String,
+        count: u32,
+        #[serde(default)]
+        recursive: bool,
+        offset: Option<u64>,
+        mode: Mode,
+    }
+
+    #[test]
+    fn parses_scalars_from_text() {
+        let form = form(&[
+            ("path", "/tmp"),
+            ("count", " 3 "),
+            ("recursive", "on"),
+            ("offset", "10"),
+            ("mode", "write"),
+        ]);
+        let args = form.deserialize::<Args>().unwrap();
+
+        assert_eq!(args.path, "/tmp");
+        assert_eq!(args.count, 3);
+        assert!(args.recursive);
+        assert_eq!(args.offset, Some(10));
+        assert_eq!(args.mode, Mode::Write);
+    }
+
+    #[test]
+    fn treats_empty_and_null_as_none() {
+        for offset in ["", "null"] {
+            let form = form(&[
+                ("path", "a"),
+                ("count", "1"),
+                ("offset", offset),
+                ("mode", "read"),
+            ]);
+
+            assert_eq!(form.deserialize::<Args>().unwrap().offset, None);
+        }
+    }
+
+    #[test]
+    fn names_the_invalid_argument() {
+        let form = form(&[("path", "a"), ("count", "many"), ("mode", "read")]);
+        let error = InvokeError::from(form.deserialize::<Args>().unwrap_err());
+
+        assert_eq!(error.code, InvokeErrorCode::InvalidArgument);
+        assert!(error.message.starts_with("Invalid `count` argument"));
+        assert_eq!(
+            error.details,
+            Some(serde_json::json!({ "argument": "count" }))
+        );
+    }
+
+    #[test]
+    fn names_the_missing_argument() {
+        let error = form(&[("count", "1"), ("mode", "read")])
+            .deserialize::<Args>()
+            .unwrap_err();
+
+        assert_eq!(error.argument.as_deref(), Some("path"));
+    }
+
+    #[test]
+    fn rejects_unknown_bools() {
+        #[derive(Debug, Deserialize)]
+        struct Flag {
+            #[allow(dead_code)]
+            flag: bool,
+        }
+
+        let error = form(&[("flag", "maybe")])
+            .deserialize::<Flag>()
+            .unwrap_err();
+
+        assert_eq!(error.argument.as_deref(), Some("flag"));
+    }
+
+    #[test]
+    fn collects_repeated_entries_into_sequences() {
+        #[derive(Deserialize)]
+        struct Paths {
+            paths: Vec<String>,
+        }
+
+        let form = form(&[("paths", "a"), ("paths", "b")]);
+
+        assert_eq!(form.deserialize::<Paths>().unwrap().paths, ["a", "b"]);
+    }
+
+    #[test]
+    fn parses_json_for_structured_entries() {
+        #[derive(Deserialize)]
+        struct Shapes {
+            point: Point,
+            points: Vec<Point>,
+        }
+
+        // Arrays arrive as one entry per item, like `json_to_form` sends them
+        let form = form(&[
+            ("point", r#"{"x":1,"y":-2}"#),
+            ("points", r#"{"x":0,"y":0}"#),
+            ("points", r#"{"x":3,"y":4}"#),
+        ]);
+        let shapes = form.deserialize::<Shapes>().unwrap();
+
+        assert_eq!(shapes.point, Point { x: 1, y: -2 });
+        assert_eq!(shapes.points, [Point { x: 0, y: 0 }, Point { x: 3, y: 4 }]);
+    }
+
+    #[test]
+    fn reads_binary_entries_as_bytes() {
+        #[derive(Deserialize)]
+        struct Data {
+            data: Vec<u8>,
+        }
+
+        let mut form = Form::new();
+        form.append(
+            "data".to_string(),
+            FormValue::Bytes(Bytes::from_static(&[0, 159, 255])),
+        );
+
+        assert_eq!(form.deserialize::<Data>().unwrap().data, [0, 159, 255]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum InvokeErrorCode {
+    InvalidArgument,
+    NotFound,
+    AlreadyExists,
+    PermissionDenied,
+    Unauthorized,
+    Conflict,
+    Cancelled,
+    Timeout,
+    RangeNotSatisfiable,
+    Unsupported,
+    Unavailable,
+    Internal,
+}
+
+impl InvokeErrorCode {
+    pub fn http_status(&self) -> u16 {
+        match self {
+            InvokeErrorCode::InvalidArgument => 400,
+            InvokeErrorCode::Unauthorized => 401,
+            InvokeErrorCode::PermissionDenied => 403,
+            InvokeErrorCode::NotFound => 404,
+            InvokeErrorCode::AlreadyExists | InvokeErrorCode::Conflict => 409,
+            InvokeErrorCode::RangeNotSatisfiable => 416,
+            InvokeErrorCode::Cancelled => 499,
+            InvokeErrorCode::Internal => 500,
+            InvokeErrorCode::Unsupported => 501,
+            InvokeErrorCode::Unavailable => 503,
+            InvokeErrorCode::Timeout => 504,
+        }
+    }
+}
+
+impl From<ErrorKind> for InvokeErrorCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NotFound => InvokeErrorCode::NotFound,
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                InvokeErrorCode::PermissionDenied
+            }
+            ErrorKind::AlreadyExists => InvokeErrorCode::AlreadyExists,
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => InvokeErrorCode::InvalidArgument,
+            ErrorKind::DirectoryNotEmpty
+            | ErrorKind::IsADirectory
+            | ErrorKind::NotADirectory
+            | ErrorKind::ResourceBusy => InvokeErrorCode::Conflict,
+            ErrorKind::TimedOut => InvokeErrorCode::Timeout,
+            ErrorKind::Interrupted => InvokeErrorCode::Cancelled,
+            ErrorKind::Unsupported => InvokeErrorCode::Unsupported,
+            ErrorKind::AddrInUse
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::WouldBlock => InvokeErrorCode::Unavailable,
+            _ => InvokeErrorCode::Internal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct InvokeError {
+    pub code: InvokeErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl InvokeError {
+    pub fn new(code: InvokeErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(InvokeErrorCode::InvalidArgument, message)
+    }
+
+    pub fn missing_argument(name: &str) -> Self {
+        Self::invalid_argument(format!("Missing `{}` argument", name))
+            .with_details(serde_json::json!({ "argument": name }))
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(InvokeErrorCode::NotFound, message)
+    }
+
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::new(InvokeErrorCode::PermissionDenied, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(InvokeErrorCode::Conflict, message)
+    }
+
+    pub fn cancelled() -> Self {
+        Self::new(InvokeErrorCode::Cancelled, "Request was cancelled")
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(InvokeErrorCode::Internal, message)
+    }
+
+    pub fn http_status(&self) -> u16 {
+        self.code.http_status()
+    }
+}
+
+impl std::fmt::Display for InvokeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for InvokeError {}
+
+impl From<std::io::Error> for InvokeError {
+    fn from(error: std::io::Error) -> Self {
+        let kind = error.kind();
+
+        Self::new(kind.into(), error.to_string())
+            .with_details(serde_json::json!({ "kind": format!("{:?}", kind) }))
+    }
+}
+
+impl From<serde_json::Error> for InvokeError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::invalid_argument(error.to_string())
+    }
+}
+
+impl From<String> for InvokeError {
+    fn from(message: String) -> Self {
+        Self::internal(message)
+    }
+}
+
+impl From<&str> for InvokeError {
+    fn from(message: &str) -> Self {
+        Self::internal(message)
+    }
+}
@@ -1,8 +1,14 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
 
 use bytes::Bytes;
 use mime_guess::Mime;
 
+use super::de::{FormDeserializer, FormError};
 
 #[derive(Debug)]
 pub struct FormFile {
@@ -11,11 +17,46 @@ pub struct FormFile {
     pub data: Bytes,
 }
 
+#[derive(Debug)]
+pub struct SpooledFile {
+    pub filename: Option<String>,
+    pub content_type: Mime,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl SpooledFile {
+    pub fn open(&self) -> std::io::Result<File> {
+        File::open(&self.path)
+    }
+
+    pub fn read(&self) -> std::io::Result<Bytes> {
+        std::fs::read(&self.path).map(Bytes::from)
+    }
+
+    pub fn persist(&self, dest: &Path) -> std::io::Result<()> {
+        if std::fs::rename(&self.path, dest).is_err() {
+            // Spool directory may live in another filesystem
+            std::fs::copy(&self.path, dest)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        // The file is already gone if it was persisted with a rename
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
 #[derive(Debug)]
 pub enum FormValue {
     Text(String),
     File(FormFile),
     Bytes(Bytes),
+    Spooled(SpooledFile),
 }
 
 impl FormValue {
@@ -39,22 +80,44 @@ impl FormValue {
             _ => None,
         }
     }
+
+    pub fn as_spooled(&self) -> Option<&SpooledFile> {
+        match self {
+            FormValue::Spooled(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    pub fn reader(&self) -> std::io::Result<Box<dyn Read + Send + '_>> {
+        match self {
+            FormValue::Text(text) => Ok(Box::new(text.as_bytes())),
+            FormValue::File(file) => Ok(Box::new(Cursor::new(&file.data))),
+            FormValue::Bytes(bytes) => Ok(Box::new(Cursor::new(bytes))),
+            FormValue::Spooled(file) => Ok(Box::new(file.open()?)),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Form {
     values: HashMap<String, Vec<FormValue>>,
 }
 
 impl Form {
     pub fn new() -> Self {
-        Self {
-            values: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn append(&mut self, key: String, value: FormValue) {
-        self.values.entry(key).or_insert_with(Vec::new).push(value);
+        self.values.entry(key).or_default().push(value);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Vec<FormValue>)> {
+        self.values.iter()
+    }
+
+    pub fn deserialize<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T, FormError> {
+        T::deserialize(FormDeserializer::new(self))
     }
 
     pub fn get_entry(&self, key: &str) -> Option<&FormValue> {
@@ -100,4 +163,12 @@ impl Form {
         self.get_entry_all(key)
             .map(|values| values.iter().filter_map(FormValue::as_bytes).collect())
     }
+
+    pub fn get_spooled(&self, key: &str) -> Option<&SpooledFile> {
+        self.get_entry(key).and_then(FormValue::as_spooled)
+    }
+
+    pub fn get_reader(&self, key: &str) -> Option<std::io::Result<Box<dyn Read + Send + '_>>> {
+        self.get_entry(key).map(FormValue::reader)
+    }
 }
\ No newline at end of file
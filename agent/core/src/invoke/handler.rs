@@ -1,8 +1,51 @@
 use std::{future::Future, pin::Pin};
 
-use super::{request::InvokeRequest, result::InvokeResult};
+use super::{cancel::CancellationToken, error::InvokeError, request::InvokeRequest, result::InvokeResult};
 
 pub type InvokeHandler = dyn Fn(InvokeRequest) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
     + 'static
     + Send
     + Sync;
+
+pub fn with_args<T, F, Fut, R>(
+    handler: F,
+) -> impl Fn(InvokeRequest) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
+where
+    T: serde::de::DeserializeOwned,
+    F: Fn(T) -> Fut + 'static + Send + Sync,
+    Fut: Future<Output = R> + 'static + Send + Sync,
+    R: Into<InvokeResult>,
+{
+    move |invoke_request| match invoke_request.args.deserialize::<T>() {
+        Ok(args) => {
+            let future = handler(args);
+            Box::pin(async move { future.await.into() })
+        }
+        Err(e) => {
+            let error = InvokeError::from(e);
+            Box::pin(async move { error.into() })
+        }
+    }
+}
+
+// Same as `with_args`, also handing over the request cancellation token
+pub fn with_args_cancellable<T, F, Fut, R>(
+    handler: F,
+) -> impl Fn(InvokeRequest) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
+where
+    T: serde::de::DeserializeOwned,
+    F: Fn(T, CancellationToken) -> Fut + 'static + Send + Sync,
+    Fut: Future<Output = R> + 'static + Send + Sync,
+    R: Into<InvokeResult>,
+{
+    move |invoke_request| match invoke_request.args.deserialize::<T>() {
+        Ok(args) => {
+            let future = handler(args, invoke_request.cancellation.clone());
+            Box::pin(async move { future.await.into() })
+        }
+        Err(e) => {
+            let error = InvokeError::from(e);
+            Box::pin(async move { error.into() })
+        }
+    }
+}
@@ -0,0 +1,57 @@
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct InvokeHandlerInfo {
+    pub command: String,
+    pub description: Option<String>,
+    pub extension: Option<String>,
+    pub args: Option<serde_json::Value>,
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl InvokeHandlerInfo {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = Some(extension.to_string());
+        self
+    }
+
+    // JSON Schema of the command arguments
+    pub fn with_args(mut self, schema: serde_json::Value) -> Self {
+        self.args = Some(schema);
+        self
+    }
+
+    // JSON Schema of the command result
+    pub fn with_result(mut self, schema: serde_json::Value) -> Self {
+        self.result = Some(schema);
+        self
+    }
+
+    // The command changes nothing, so it can also be invoked with a plain
+    // GET, from `<img src>` and alike
+    pub fn with_readonly(mut self) -> Self {
+        self.readonly = true;
+        self
+    }
+
+    // The command only runs once the user allows it in a page served by the
+    // agent itself, see `app.confirm`. It can't be invoked directly.
+    pub fn with_confirmation(mut self) -> Self {
+        self.confirm = true;
+        self
+    }
+}
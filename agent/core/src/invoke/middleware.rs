@@ -0,0 +1,40 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use super::{handler::InvokeHandler, request::InvokeRequest, result::InvokeResult};
+
+pub type InvokeMiddleware = dyn Fn(InvokeRequest, Next) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
+    + 'static
+    + Send
+    + Sync;
+
+// Remaining part of the chain, calling `run` hands the request to the next
+// middleware or, at the end of the chain, to the command handler.
+#[derive(Clone)]
+pub struct Next {
+    middlewares: Arc<Vec<Arc<InvokeMiddleware>>>,
+    handler: Arc<InvokeHandler>,
+    index: usize,
+}
+
+impl Next {
+    pub fn new(middlewares: Arc<Vec<Arc<InvokeMiddleware>>>, handler: Arc<InvokeHandler>) -> Self {
+        Self {
+            middlewares,
+            handler,
+            index: 0,
+        }
+    }
+
+    pub fn run(
+        mut self,
+        request: InvokeRequest,
+    ) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>> {
+        match self.middlewares.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware(request, self)
+            }
+            None => (self.handler)(request),
+        }
+    }
+}
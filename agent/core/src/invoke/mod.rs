@@ -1,8 +1,22 @@
+mod cancel;
+mod de;
+mod error;
 mod handler;
+mod info;
+mod middleware;
+mod range;
 mod result;
 mod request;
+mod stream;
 
 pub mod form;
-pub use handler::InvokeHandler;
+pub use cancel::{CancellationToken, Cancelled, DropGuard};
+pub use de::FormError;
+pub use error::{InvokeError, InvokeErrorCode};
+pub use handler::{with_args, with_args_cancellable, InvokeHandler};
+pub use info::InvokeHandlerInfo;
+pub use middleware::{InvokeMiddleware, Next};
+pub use range::ByteRange;
 pub use result::InvokeResult;
-pub use request::InvokeRequest;
\ No newline at end of file
+pub use request::InvokeRequest;
+pub use stream::InvokeStream;
\ No newline at end of file
@@ -0,0 +1,230 @@
+use serde_json::json;
+
+use super::error::{InvokeError, InvokeErrorCode};
+
+// Bytes `start..end` of a resource that is `size` bytes long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+    pub size: u64,
+}
+
+impl ByteRange {
+    // `length` bytes from `offset`, or up to the end. Cut short at the end of
+    // the resource, like a read would.
+    pub fn new(offset: u64, length: Option<u64>, size: u64) -> Result<Self, InvokeError> {
+        if offset > size {
+            return Err(not_satisfiable(size));
+        }
+
+        let end = match length {
+            Some(length) => offset.saturating_add(length).min(size),
+            None => size,
+        };
+
+        Ok(Self {
+            start: offset,
+            end,
+            size,
+        })
+    }
+
+    // A `Range` header value, like `bytes=0-1023`, `bytes=1024-` or
+    // `bytes=-1024`. Only the first range of a multi range request is served.
+    // The range is never empty, those are not satisfiable.
+    pub fn parse(header: &str, size: u64) -> Result<Self, InvokeError> {
+        let invalid = || {
+            InvokeError::invalid_argument(format!("Invalid range: {}", header))
+                .with_details(json!({ "argument": "range" }))
+        };
+
+        let spec = header
+            .trim()
+            .strip_prefix("bytes=")
+            .and_then(|ranges| ranges.split(',').next())
+            .ok_or_else(invalid)?;
+
+        let (start, end) = spec.trim().split_once('-').ok_or_else(invalid)?;
+        let number = |value: &str| value.parse::<u64>().map_err(|_| invalid());
+
+        match (start, end) {
+            ("", "") => Err(invalid()),
+            // The last `suffix` bytes
+            ("", suffix) => {
+                let suffix = number(suffix)?;
+
+                // Nothing to serve from an empty resource either
+                if suffix == 0 || size == 0 {
+                    return Err(not_satisfiable(size));
+                }
+
+                Self::new(size.saturating_sub(suffix), None, size)
+            }
+            (start, "") => Self::from_start(number(start)?, size),
+            (start, end) => {
+                let (start, end) = (number(start)?, number(end)?);
+
+                if end < start {
+                    return Err(invalid());
+                }
+
+                // HTTP ranges include their last byte
+                Self::from_start(start, size).map(|range| Self {
+                    end: end.saturating_add(1).min(size),
+                    ..range
+                })
+            }
+        }
+    }
+
+    fn from_start(start: u64, size: u64) -> Result<Self, InvokeError> {
+        if start >= size {
+            return Err(not_satisfiable(size));
+        }
+
+        Self::new(start, None, size)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // `Content-Range` header value. Only ranges from `parse` are answered as
+    // partial content, and those are never empty.
+    pub fn content_range(&self) -> String {
+        debug_assert!(!self.is_empty(), "empty ranges are not satisfiable");
+
+        format!("bytes {}-{}/{}", self.start, self.end - 1, self.size)
+    }
+}
+
+fn not_satisfiable(size: u64) -> InvokeError {
+    InvokeError::new(
+        InvokeErrorCode::RangeNotSatisfiable,
+        format!("Range is outside of the {} bytes available", size),
+    )
+    .with_details(json!({ "size": size }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64, size: u64) -> ByteRange {
+        ByteRange { start, end, size }
+    }
+
+    fn code(result: Result<ByteRange, InvokeError>) -> InvokeErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn parses_bounded_ranges() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-99", 1000).unwrap(),
+            range(0, 100, 1000)
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=10-10", 1000).unwrap(),
+            range(10, 11, 1000)
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=900-2000", 1000).unwrap(),
+            range(900, 1000, 1000)
+        );
+    }
+
+    #[test]
+    fn parses_open_and_suffix_ranges() {
+        assert_eq!(
+            ByteRange::parse("bytes=100-", 1000).unwrap(),
+            range(100, 1000, 1000)
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-100", 1000).unwrap(),
+            range(900, 1000, 1000)
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=-5000", 1000).unwrap(),
+            range(0, 1000, 1000)
+        );
+    }
+
+    #[test]
+    fn serves_only_the_first_range() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-9, 20-29", 100).unwrap(),
+            range(0, 10, 100)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        for header in [
+            "",
+            "0-9",
+            "bytes=",
+            "bytes=-",
+            "bytes=a-9",
+            "bytes=9-0",
+            "items=0-9",
+        ] {
+            assert_eq!(
+                code(ByteRange::parse(header, 100)),
+                InvokeErrorCode::InvalidArgument,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_ranges_outside_of_the_resource() {
+        for header in ["bytes=100-", "bytes=100-200", "bytes=-0"] {
+            assert_eq!(
+                code(ByteRange::parse(header, 100)),
+                InvokeErrorCode::RangeNotSatisfiable,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_every_range_of_an_empty_resource() {
+        for header in ["bytes=0-", "bytes=0-0", "bytes=-1", "bytes=-100"] {
+            assert_eq!(
+                code(ByteRange::parse(header, 0)),
+                InvokeErrorCode::RangeNotSatisfiable,
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn cuts_offset_reads_at_the_end() {
+        assert_eq!(
+            ByteRange::new(10, Some(20), 100).unwrap(),
+            range(10, 30, 100)
+        );
+        assert_eq!(
+            ByteRange::new(90, Some(20), 100).unwrap(),
+            range(90, 100, 100)
+        );
+        assert_eq!(ByteRange::new(10, None, 100).unwrap(), range(10, 100, 100));
+        assert!(ByteRange::new(100, None, 100).unwrap().is_empty());
+        assert_eq!(
+            code(ByteRange::new(101, None, 100)),
+            InvokeErrorCode::RangeNotSatisfiable
+        );
+    }
+
+    #[test]
+    fn formats_content_range_with_the_last_byte() {
+        assert_eq!(range(0, 100, 1000).content_range(), "bytes 0-99/1000");
+        assert_eq!(range(999, 1000, 1000).content_range(), "bytes 999-999/1000");
+    }
+}
@@ -1,7 +1,26 @@
-use super::form::Form;
+use super::{cancel::CancellationToken, form::Form};
 
 #[derive(Debug)]
 pub struct InvokeRequest {
     pub command: String,
     pub args: Form,
-}
\ No newline at end of file
+    pub cancellation: CancellationToken,
+    // The channel connection the invoke came through, none over HTTP
+    pub connection: Option<u64>,
+}
+
+impl InvokeRequest {
+    pub fn new(command: String, args: Form) -> Self {
+        Self {
+            command,
+            args,
+            cancellation: CancellationToken::new(),
+            connection: None,
+        }
+    }
+
+    pub fn with_connection(mut self, connection: u64) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+}
@@ -2,13 +2,21 @@ use std::io::Error;
 
 use bytes::Bytes;
 
+use super::{error::InvokeError, range::ByteRange, stream::InvokeStream};
+
 pub enum InvokeResult {
     Json(serde_json::Value),
     Text(String),
     Binary(Bytes),
-    Error(String),
+    Stream(InvokeStream),
+    // Part of a bigger resource, answered with `206 Partial Content` over HTTP
+    Partial(ByteRange, InvokeStream),
+    Error(InvokeError),
     Void,
     Quit,
+    // Another result plus fields about it, like the version of a file that
+    // was read. Sent as the `X-Invoke-Meta` header over HTTP.
+    WithMeta(Box<InvokeResult>, serde_json::Value),
 }
 
 impl InvokeResult {
@@ -17,60 +25,134 @@ impl InvokeResult {
             InvokeResult::Json(_) => "json",
             InvokeResult::Text(_) => "text",
             InvokeResult::Binary(_) => "binary",
+            InvokeResult::Stream(_) | InvokeResult::Partial(..) => "stream",
             InvokeResult::Void => "void",
             InvokeResult::Error(_) => "error",
             InvokeResult::Quit => "void",
+            InvokeResult::WithMeta(result, _) => result.label(),
+        }
+    }
+
+    pub fn with_meta(self, meta: serde_json::Value) -> Self {
+        InvokeResult::WithMeta(Box::new(self), meta)
+    }
+
+    // The result itself and the meta attached to it, if any. Fields of outer
+    // metas win over the ones of results they wrap.
+    pub fn into_parts(self) -> (InvokeResult, Option<serde_json::Value>) {
+        match self {
+            InvokeResult::WithMeta(result, mut meta) => match result.into_parts() {
+                (result, Some(serde_json::Value::Object(inner))) => {
+                    if let serde_json::Value::Object(outer) = &mut meta {
+                        for (key, value) in inner {
+                            outer.entry(key).or_insert(value);
+                        }
+                    }
+
+                    (result, Some(meta))
+                }
+                (result, _) => (result, Some(meta)),
+            },
+            result => (result, None),
         }
     }
 }
 
-impl Into<InvokeResult> for serde_json::Value {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Json(self)
+impl From<serde_json::Value> for InvokeResult {
+    fn from(value: serde_json::Value) -> Self {
+        InvokeResult::Json(value)
+    }
+}
+
+impl From<String> for InvokeResult {
+    fn from(value: String) -> Self {
+        InvokeResult::Text(value)
     }
 }
 
-impl Into<InvokeResult> for String {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Text(self)
+impl From<Bytes> for InvokeResult {
+    fn from(value: Bytes) -> Self {
+        InvokeResult::Binary(value)
     }
 }
 
-impl Into<InvokeResult> for Bytes {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Binary(self)
+impl From<InvokeStream> for InvokeResult {
+    fn from(value: InvokeStream) -> Self {
+        InvokeResult::Stream(value)
     }
 }
 
-impl Into<InvokeResult> for () {
-    fn into(self) -> InvokeResult {
+impl From<()> for InvokeResult {
+    fn from(_: ()) -> Self {
         InvokeResult::Void
     }
 }
 
-impl Into<InvokeResult> for Vec<u8> {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Binary(self.into())
+impl From<Vec<u8>> for InvokeResult {
+    fn from(value: Vec<u8>) -> Self {
+        InvokeResult::Binary(value.into())
     }
 }
 
-impl Into<InvokeResult> for &str {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Text(self.to_string())
+impl From<&str> for InvokeResult {
+    fn from(value: &str) -> Self {
+        InvokeResult::Text(value.to_string())
     }
 }
 
-impl Into<InvokeResult> for Error {
-    fn into(self) -> InvokeResult {
-        InvokeResult::Error(self.to_string())
+impl From<Error> for InvokeResult {
+    fn from(value: Error) -> Self {
+        InvokeResult::Error(value.into())
     }
 }
 
-impl<T: Into<InvokeResult>, E: ToString> Into<InvokeResult> for Result<T, E> {
-    fn into(self) -> InvokeResult {
-        match self {
+impl From<InvokeError> for InvokeResult {
+    fn from(value: InvokeError) -> Self {
+        InvokeResult::Error(value)
+    }
+}
+
+impl<T: Into<InvokeResult>, E: Into<InvokeError>> From<Result<T, E>> for InvokeResult {
+    fn from(value: Result<T, E>) -> Self {
+        match value {
             Ok(value) => value.into(),
-            Err(message) => InvokeResult::Error(message.to_string()),
+            Err(error) => InvokeResult::Error(error.into()),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keeps_results_without_meta() {
+        let (result, meta) = InvokeResult::Void.into_parts();
+
+        assert!(matches!(result, InvokeResult::Void));
+        assert!(meta.is_none());
+    }
+
+    #[test]
+    fn takes_meta_apart() {
+        let result = InvokeResult::from("hi").with_meta(json!({ "version": "1" }));
+        assert_eq!(result.label(), "text");
+
+        let (result, meta) = result.into_parts();
+        assert!(matches!(result, InvokeResult::Text(text) if text == "hi"));
+        assert_eq!(meta, Some(json!({ "version": "1" })));
+    }
+
+    #[test]
+    fn merges_nested_meta() {
+        let result = InvokeResult::Void
+            .with_meta(json!({ "version": "1", "size": 2 }))
+            .with_meta(json!({ "version": "3" }));
+
+        let (result, meta) = result.into_parts();
+        assert!(matches!(result, InvokeResult::Void));
+        assert_eq!(meta, Some(json!({ "version": "3", "size": 2 })));
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{Error, ErrorKind, Read};
+
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, Stream, StreamExt};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// How many chunks a reader may get ahead of the client
+const READ_AHEAD: usize = 4;
+
+pub enum InvokeStream {
+    Bytes(BoxStream<'static, Result<Bytes, Error>>),
+    JsonLines(BoxStream<'static, Result<serde_json::Value, Error>>),
+    // Read by the agent on its blocking threads, plugins can't spawn on the
+    // agent runtime and their own tokio has none
+    Reader(Box<dyn Read + Send>),
+}
+
+impl InvokeStream {
+    pub fn bytes<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<Bytes, Error>> + Send + 'static,
+    {
+        InvokeStream::Bytes(stream.boxed())
+    }
+
+    pub fn json_lines<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<serde_json::Value, Error>> + Send + 'static,
+    {
+        InvokeStream::JsonLines(stream.boxed())
+    }
+
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        InvokeStream::Reader(Box::new(reader))
+    }
+
+    pub fn from_values<I, T>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: Send + 'static,
+        T: serde::Serialize,
+    {
+        let values = iter
+            .into_iter()
+            .map(|value| serde_json::to_value(value).map_err(Error::other));
+
+        InvokeStream::json_lines(futures_util::stream::iter(values))
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            InvokeStream::Bytes(_) | InvokeStream::Reader(_) => "bytes",
+            InvokeStream::JsonLines(_) => "json-lines",
+        }
+    }
+
+    // Must be called from the agent runtime when the stream is a reader
+    pub fn into_bytes(self) -> BoxStream<'static, Result<Bytes, Error>> {
+        match self {
+            InvokeStream::Bytes(stream) => stream,
+            InvokeStream::Reader(reader) => read_in_background(reader).boxed(),
+            InvokeStream::JsonLines(stream) => stream
+                .map(|value| {
+                    let mut line = serde_json::to_vec(&value?).map_err(Error::other)?;
+                    line.push(b'\n');
+
+                    Ok(Bytes::from(line))
+                })
+                .boxed(),
+        }
+    }
+}
+
+// Reads ahead up to `READ_AHEAD` chunks, waiting while the consumer is
+// behind. Dropping the stream stops the reads.
+fn read_in_background(
+    mut reader: Box<dyn Read + Send>,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(READ_AHEAD);
+
+    tokio::task::spawn_blocking(move || loop {
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+
+        let chunk = match reader.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(read) => {
+                buffer.truncate(read);
+                Ok(Bytes::from(buffer))
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+
+        let failed = chunk.is_err();
+
+        if sender.blocking_send(chunk).is_err() || failed {
+            return;
+        }
+    });
+
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(stream: InvokeStream) -> Vec<Bytes> {
+        stream
+            .into_bytes()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn reads_readers_in_chunks() {
+        let data = (0..READ_CHUNK_SIZE * 2 + 10)
+            .map(|index| index as u8)
+            .collect::<Vec<_>>();
+
+        let chunks = collect(InvokeStream::from_reader(std::io::Cursor::new(
+            data.clone(),
+        )))
+        .await;
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn ends_readers_on_errors() {
+        let reader = std::io::Cursor::new(b"abc".to_vec()).chain(FailingReader);
+        let mut stream = InvokeStream::from_reader(reader).into_bytes();
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "abc");
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+
+    // Would hang the runtime shutdown if the reading thread kept going
+    #[tokio::test]
+    async fn stops_reading_once_dropped() {
+        let mut stream = InvokeStream::from_reader(std::io::repeat(1)).into_bytes();
+
+        assert!(stream.next().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn streams_values_as_json_lines() {
+        let chunks = collect(InvokeStream::from_values([1, 2])).await;
+
+        assert_eq!(chunks.concat(), b"1\n2\n");
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(Error::other("disk on fire"))
+        }
+    }
+}
@@ -1,3 +1,4 @@
+pub mod events;
 pub mod extensions;
 pub mod macros;
 pub mod invoke;
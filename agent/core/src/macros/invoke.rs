@@ -1,15 +1,36 @@
+// Each entry is `name => handler`, where the handler takes the raw
+// `InvokeRequest`, or `name => typed handler` for a handler taking its
+// arguments already deserialized, see `with_args`. `typed cancellable` also
+// hands over the cancellation token, like `with_args_cancellable`.
 #[macro_export]
 macro_rules! define_invoke_handlers {
-    ($($name:expr => $handler:expr),*) => {
+    (@insert $handlers:ident;) => {};
+    (@insert $handlers:ident; $name:expr => typed cancellable $handler:expr $(, $($rest:tt)*)?) => {
+        $handlers.insert(
+            $name.into(),
+            std::sync::Arc::new(lenz_core::invoke::with_args_cancellable($handler)),
+        );
+        lenz_core::define_invoke_handlers!(@insert $handlers; $($($rest)*)?);
+    };
+    (@insert $handlers:ident; $name:expr => typed $handler:expr $(, $($rest:tt)*)?) => {
+        $handlers.insert(
+            $name.into(),
+            std::sync::Arc::new(lenz_core::invoke::with_args($handler)),
+        );
+        lenz_core::define_invoke_handlers!(@insert $handlers; $($($rest)*)?);
+    };
+    (@insert $handlers:ident; $name:expr => $handler:expr $(, $($rest:tt)*)?) => {
+        $handlers.insert($name.into(), std::sync::Arc::new(|invoke_request| Box::pin(async {
+            let result: lenz_core::invoke::InvokeResult = $handler(invoke_request).await.into();
+            return result;
+        })));
+        lenz_core::define_invoke_handlers!(@insert $handlers; $($($rest)*)?);
+    };
+    ($($entries:tt)*) => {
         {
             let mut handlers: std::collections::HashMap<String, std::sync::Arc<lenz_core::invoke::InvokeHandler>> = std::collections::HashMap::new();
 
-            $(
-                handlers.insert($name.into(), std::sync::Arc::new(|invoke_request| Box::pin(async {
-                    let result: lenz_core::invoke::InvokeResult = $handler(invoke_request).await.into();
-                    return result;
-                })));
-            )*
+            lenz_core::define_invoke_handlers!(@insert handlers; $($entries)*);
 
             handlers
         }
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    ffi::CStr,
     fs::{self, DirEntry},
     io::Error,
     path::PathBuf,
@@ -7,30 +8,56 @@ use std::{
 };
 
 use lenz_core::{
-    config::consts::BASE_URL,
     define_invoke_handlers,
-    extensions::plugin::{LenzPlugin, LenzPluginContext},
-    invoke::InvokeResult,
+    events::EventBus,
+    extensions::{
+        manifest::ExtensionError,
+        plugin::{self, LenzPluginContext, LenzPluginDeclaration, LenzPluginHandle},
+    },
+    invoke::{with_args, InvokeError, InvokeHandlerInfo, InvokeRequest, InvokeResult},
 };
 use libloading::Library;
 
+use crate::auth::Auth;
+use crate::confirm::Confirmations;
 use crate::state::{
     extensions::{Extension, ExtensionHost},
     invoke_handlers::InvokeHandlers,
+    middlewares,
     static_assets::StaticAssets,
 };
 
+// Events clients can emit, the others come from the agent and extensions
+// and are trusted by their listeners
+const CLIENT_EVENT_PREFIX: &str = "client.";
+
 pub struct AppState {
-    #[allow(dead_code)]
     pub config: Arc<lenz_core::config::AgentConfig>,
     pub static_files: tokio::sync::RwLock<StaticAssets>,
     pub extension_host: tokio::sync::RwLock<ExtensionHost>,
     pub import_map: tokio::sync::RwLock<HashMap<String, String>>,
     pub invoke_handlers: tokio::sync::RwLock<InvokeHandlers>,
+    pub events: EventBus,
+    pub auth: Arc<Auth>,
+    pub confirmations: Arc<Confirmations>,
 }
 
 pub type App = Arc<AppState>;
 
+#[derive(serde::Deserialize)]
+struct ConfirmArgs {
+    command: String,
+    #[serde(default)]
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(serde::Deserialize)]
+struct TicketArgs {
+    command: String,
+    #[serde(default)]
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
 // one possible implementation of walking a directory only visiting files
 fn visit_dirs(dir: &PathBuf, cb: &mut dyn FnMut(&DirEntry)) -> Result<(), Error> {
     if dir.is_dir() {
@@ -50,12 +77,13 @@ fn visit_dirs(dir: &PathBuf, cb: &mut dyn FnMut(&DirEntry)) -> Result<(), Error>
 pub fn search_esm_files(
     dir: &PathBuf,
     user_extension: Option<&Extension>,
+    server_url: &str,
 ) -> HashMap<String, String> {
     let (prefix_name, prefix_url) = user_extension
         .map(|ext| {
             let id = ext.id();
             let public_url = ext.base_url();
-            let relative_path = dir.strip_prefix(&ext.dir()).unwrap().to_str().unwrap();
+            let relative_path = dir.strip_prefix(ext.dir()).unwrap().to_str().unwrap();
             let script_url = format!("{public_url}/{relative_path}");
 
             if ext.is_builtin() {
@@ -63,7 +91,7 @@ pub fn search_esm_files(
             }
             (format!("ext/{id}"), script_url)
         })
-        .unwrap_or_else(|| ("".to_string(), format!("{BASE_URL}/esm")));
+        .unwrap_or_else(|| ("".to_string(), format!("{server_url}/esm")));
 
     let mut import_map: HashMap<String, String> = HashMap::new();
 
@@ -103,11 +131,15 @@ pub fn search_esm_files(
 }
 
 impl AppState {
-    pub fn new() -> Arc<Self> {
-        let config = Arc::new(lenz_core::config::AgentConfig::load());
+    pub fn new(config: lenz_core::config::AgentConfig) -> Arc<Self> {
+        let config = Arc::new(config);
+        let server_url = config.server.base_url();
         let mut static_assets = StaticAssets::new(config.www_dir.clone());
 
         let mut invoke_handlers = InvokeHandlers::new();
+        let events = EventBus::new();
+        let auth = Arc::new(Auth::new(&config.server));
+        let confirmations = Arc::new(Confirmations::default());
 
         invoke_handlers.extend(define_invoke_handlers! {
             "app.quit" => |_| async {
@@ -115,18 +147,195 @@ impl AppState {
             }
         });
 
+        let emitter = events.clone();
+
+        invoke_handlers.add("app.emit", move |invoke: InvokeRequest| {
+            let emitter = emitter.clone();
+
+            Box::pin(async move {
+                let event = match invoke.args.get_text("event") {
+                    Some(event) => event,
+                    None => return InvokeError::missing_argument("event").into(),
+                };
+
+                if !event.starts_with(CLIENT_EVENT_PREFIX) {
+                    return InvokeError::invalid_argument(format!(
+                        "Event must start with {}: {}",
+                        CLIENT_EVENT_PREFIX, event
+                    ))
+                    .with_details(serde_json::json!({ "argument": "event" }))
+                    .into();
+                }
+
+                let data: serde_json::Value = invoke
+                    .args
+                    .get_text("data")
+                    .map(|data| serde_json::from_str(data).unwrap_or_else(|_| data.into()))
+                    .unwrap_or_default();
+
+                emitter.emit(event, data);
+
+                InvokeResult::Void
+            })
+        });
+
+        let catalog = invoke_handlers.catalog();
+
+        invoke_handlers.add("app.commands", move |_| {
+            let catalog = catalog.clone();
+
+            Box::pin(async move { serde_json::json!(catalog.list()).into() })
+        });
+
+        let catalog = invoke_handlers.catalog();
+        let ticket_auth = auth.clone();
+
+        invoke_handlers.add(
+            "app.ticket",
+            with_args(move |TicketArgs { command, args }| {
+                let readonly = catalog.get(&command).is_some_and(|info| info.readonly);
+                let auth = ticket_auth.clone();
+
+                async move {
+                    if !readonly {
+                        return Err(InvokeError::invalid_argument(format!(
+                            "Command {} can't be invoked with GET",
+                            command
+                        ))
+                        .with_details(serde_json::json!({ "argument": "command" })));
+                    }
+
+                    let query = args
+                        .iter()
+                        .map(|(key, value)| {
+                            let value = match value {
+                                serde_json::Value::String(value) => value.clone(),
+                                value => value.to_string(),
+                            };
+
+                            format!("{}={}", urlencoding::encode(key), urlencoding::encode(&value))
+                        })
+                        .collect::<Vec<_>>()
+                        .join("&");
+
+                    let url = auth.issue_ticket(&format!("/_invoke/{command}?{query}"));
+
+                    Ok(serde_json::json!({ "url": url }))
+                }
+            }),
+        );
+
+        let catalog = invoke_handlers.catalog();
+        let pending = confirmations.clone();
+        let confirm_events = events.clone();
+
+        invoke_handlers.add(
+            "app.confirm",
+            with_args(move |ConfirmArgs { command, args }| {
+                let confirm = catalog.get(&command).is_some_and(|info| info.confirm);
+                let id =
+                    confirm.then(|| pending.request(command.clone(), args, confirm_events.clone()));
+
+                async move {
+                    match id {
+                        Some(id) => Ok(serde_json::json!({
+                            "id": id,
+                            "url": format!("/{}{}", crate::confirm::PREFIX, id)
+                        })),
+                        None => Err(InvokeError::invalid_argument(format!(
+                            "Command {} doesn't need confirmation, invoke it directly",
+                            command
+                        ))
+                        .with_details(serde_json::json!({ "argument": "command" }))),
+                    }
+                }
+            }),
+        );
+
+        invoke_handlers.describe(
+            InvokeHandlerInfo::new("app.quit").with_description("Stops the agent server"),
+        );
+        invoke_handlers.describe(
+            InvokeHandlerInfo::new("app.emit")
+                .with_description("Publishes a client.* event on the event bus")
+                .with_args(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "event": { "type": "string", "pattern": "^client\\." },
+                        "data": {}
+                    },
+                    "required": ["event"]
+                })),
+        );
+        invoke_handlers.describe(
+            InvokeHandlerInfo::new("app.commands")
+                .with_description("Lists the registered invoke commands")
+                .with_result(serde_json::json!({
+                    "type": "array",
+                    "items": { "type": "object" }
+                })),
+        );
+        invoke_handlers.describe(
+            InvokeHandlerInfo::new("app.confirm")
+                .with_description(
+                    "Asks the user to allow a command that needs confirmation, answered in the page at `url`",
+                )
+                .with_args(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "string" },
+                        "args": { "type": "object" }
+                    },
+                    "required": ["command"]
+                }))
+                .with_result(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string" },
+                        "url": { "type": "string" }
+                    }
+                })),
+        );
+        invoke_handlers.describe(
+            InvokeHandlerInfo::new("app.ticket")
+                .with_description(
+                    "Creates a URL invoking a readonly command with GET, without the session token",
+                )
+                .with_args(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "string" },
+                        "args": { "type": "object" }
+                    },
+                    "required": ["command"]
+                }))
+                .with_result(serde_json::json!({
+                    "type": "object",
+                    "properties": { "url": { "type": "string" } }
+                })),
+        );
+
+        if config.log_invokes {
+            invoke_handlers.wrap(middlewares::logger);
+        }
+
+        invoke_handlers.wrap(middlewares::required_args(invoke_handlers.catalog()));
+
         static_assets.add("/vendor/", config.vendor_dir.clone());
         static_assets.add("/esm/", config.esm_dir.clone());
 
-        let mut importmap = search_esm_files(&config.esm_dir, None);
+        let mut importmap = search_esm_files(&config.esm_dir, None, &server_url);
 
-        importmap.insert("vue".into(), "http://localhost:5369/vendor/vue.js".into());
+        importmap.insert("vue".into(), format!("{server_url}/vendor/vue.js"));
 
         Arc::new(Self {
             import_map: tokio::sync::RwLock::new(importmap),
-            extension_host: tokio::sync::RwLock::new(ExtensionHost::new(config.clone())),
+            extension_host: tokio::sync::RwLock::new(ExtensionHost::new(config.clone(), events.clone())),
             static_files: tokio::sync::RwLock::new(static_assets),
             invoke_handlers: tokio::sync::RwLock::new(invoke_handlers),
+            events,
+            auth,
+            confirmations,
             config,
         })
     }
@@ -136,24 +345,87 @@ impl AppState {
     }
 }
 
+// Plugins declare the ABI, compiler and dependencies they were built with,
+// anything that doesn't match the agent exactly is refused before running any
+// of its code
+unsafe fn check_declaration(declaration: &LenzPluginDeclaration) -> Result<(), String> {
+    if declaration.abi_version != plugin::ABI_VERSION {
+        return Err(format!(
+            "Plugin ABI version {} is not supported, the agent uses version {}",
+            declaration.abi_version,
+            plugin::ABI_VERSION
+        ));
+    }
+
+    let expected = [
+        ("lenz_core", declaration.core_version, plugin::CORE_VERSION),
+        ("rustc", declaration.rustc_version, plugin::RUSTC_VERSION),
+        // Rebuilding the plugin with the agent's Cargo.lock makes these match
+        ("dependencies", declaration.dependencies, plugin::DEPENDENCIES),
+    ];
+
+    for (name, actual, expected) in expected {
+        let actual = CStr::from_ptr(actual).to_string_lossy();
+        let expected = expected.trim_end_matches('\0');
+
+        if actual != expected {
+            return Err(format!(
+                "Plugin was built with {} {}, the agent with {}",
+                name, actual, expected
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Whatever a plugin registered before failing points into the library, which
+// is about to be unloaded
+fn discard_registrations(context: &mut LenzPluginContext) {
+    context.invoke_handlers.clear();
+    context.invoke_handlers_info.clear();
+
+    for subscription in context.subscriptions.drain(..) {
+        context.events.off(&subscription);
+    }
+}
+
+// Manifests name the library without the platform prefix and extension,
+// `dynlib/name` is `dynlib/libname.so` on Linux
+pub fn dynlib_path(path: PathBuf) -> PathBuf {
+    let base_name = path.file_name().unwrap_or_default();
+    path.with_file_name(libloading::library_filename(base_name))
+}
+
 pub fn load_dynlib_extension(
     lib_path: PathBuf,
     context: &mut LenzPluginContext,
-) -> Result<(Box<dyn LenzPlugin>, Library), Box<dyn std::error::Error>> {
-    let base_name = lib_path.file_name().unwrap_or_default();
-    let dirname = lib_path
-        .parent()
-        .expect("Library path must have a parent directory");
-    let lib_path = dirname.join(libloading::library_filename(base_name.to_str().unwrap()));
+) -> Result<(LenzPluginHandle, Library), ExtensionError> {
+    let fail = |message: String| ExtensionError::FailedToLoadDynlib(message);
 
     unsafe {
-        let lib = libloading::Library::new(lib_path).expect("Failed to load library");
-        let create_plugin: libloading::Symbol<fn(&mut LenzPluginContext) -> *mut dyn LenzPlugin> =
-            lib.get(b"create_plugin").expect("Failed to load symbol");
+        let lib = libloading::Library::new(lib_path).map_err(|e| fail(e.to_string()))?;
+        let declaration: libloading::Symbol<*const LenzPluginDeclaration> =
+            lib.get(b"LENZ_PLUGIN").map_err(|e| {
+                fail(format!(
+                    "Missing plugin declaration, see `declare_plugin!`: {}",
+                    e
+                ))
+            })?;
+        let declaration = &**declaration;
+
+        check_declaration(declaration).map_err(fail)?;
 
-        let mut plugin = Box::from_raw(create_plugin(context));
+        let mut plugin = (declaration.create)();
 
-        plugin.activate(context);
+        if plugin.is_empty() {
+            return Err(fail("Plugin panicked while being created".to_string()));
+        }
+
+        if !plugin.activate(context) {
+            discard_registrations(context);
+            return Err(fail("Plugin panicked while being activated".to_string()));
+        }
 
         Ok((plugin, lib))
     }
@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http::{
+    header::{HOST, ORIGIN, REFERER},
+    Request,
+};
+use lenz_core::config::ServerConfig;
+use rand::RngCore;
+
+pub const TOKEN_HEADER: &str = "X-Lenz-Token";
+
+// Tickets not used for this long are dropped, media elements keep theirs
+// alive while they read
+const TICKET_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+struct Ticket {
+    // The only path and query the ticket opens, as issued
+    url: String,
+    last_used: Instant,
+}
+
+pub struct Auth {
+    token: String,
+    allowed_hosts: Vec<String>,
+    allowed_origins: Vec<String>,
+    tickets: Mutex<HashMap<String, Ticket>>,
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Avoids leaking how much of the token matched through response timing
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn header<B>(req: &Request<B>, name: impl http::header::AsHeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+impl Auth {
+    pub fn new(config: &ServerConfig) -> Self {
+        let port = config.port;
+
+        let mut allowed_hosts = [
+            config.addr(),
+            format!("localhost:{port}"),
+            format!("127.0.0.1:{port}"),
+            format!("[::1]:{port}"),
+        ]
+        .to_vec();
+
+        // Browsers leave the default port out of the Host header
+        if port == 80 {
+            allowed_hosts.extend(
+                [config.host.as_str(), "localhost", "127.0.0.1", "[::1]"].map(String::from),
+            );
+        }
+
+        let allowed_origins = allowed_hosts
+            .iter()
+            .map(|host| format!("http://{host}"))
+            .chain(config.allowed_origins.iter().cloned())
+            .collect();
+
+        Self {
+            // A fixed token is handy for tools started alongside the agent
+            token: std::env::var("LENZ_AUTH_TOKEN").unwrap_or_else(|_| generate_token()),
+            allowed_hosts,
+            allowed_origins,
+            tickets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    // Rejects DNS rebinding, where another site resolves to the agent address
+    pub fn is_allowed_host<B>(&self, req: &Request<B>) -> bool {
+        header(req, HOST)
+            .map(|host| self.allowed_hosts.iter().any(|allowed| allowed == host))
+            .unwrap_or(false)
+    }
+
+    // The browser origin of the request, from `Origin` or, for plain
+    // requests like `<script src>`, from `Referer`
+    fn request_origin<B>(req: &Request<B>) -> Option<String> {
+        if let Some(origin) = header(req, ORIGIN) {
+            return Some(origin.to_string());
+        }
+
+        header(req, REFERER)
+            .map(|referer| referer.splitn(4, '/').take(3).collect::<Vec<_>>().join("/"))
+    }
+
+    // Ok with the origin to be allowed through CORS, Err when the request
+    // comes from a page that isn't allowed to talk to the agent
+    pub fn check_origin<B>(&self, req: &Request<B>) -> Result<Option<String>, ()> {
+        match Self::request_origin(req) {
+            Some(origin) if self.allowed_origins.contains(&origin) => Ok(Some(origin)),
+            Some(_) => Err(()),
+            // Without Origin or Referer only trust non cross-site requests,
+            // tools like curl don't send `Sec-Fetch-Site` at all
+            None => match header(req, "Sec-Fetch-Site") {
+                Some("cross-site") | Some("same-site") => Err(()),
+                _ => Ok(None),
+            },
+        }
+    }
+
+    // Only the `Origin` header, which pages can't leave out or fake for
+    // requests made in CORS mode, like `<script crossorigin>`
+    pub fn has_allowed_origin<B>(&self, req: &Request<B>) -> bool {
+        header(req, ORIGIN)
+            .map(|origin| self.allowed_origins.iter().any(|allowed| allowed == origin))
+            .unwrap_or(false)
+    }
+
+    pub fn has_valid_token<B>(&self, req: &Request<B>) -> bool {
+        header(req, TOKEN_HEADER)
+            .map(|token| constant_time_eq(token, &self.token))
+            .unwrap_or(false)
+    }
+
+    // Browsers can't set headers when opening a WebSocket, the token comes in
+    // the query of the upgrade request only
+    pub fn has_valid_query_token<B>(&self, req: &Request<B>) -> bool {
+        query_param(req, "token")
+            .map(|token| constant_time_eq(token, &self.token))
+            .unwrap_or(false)
+    }
+
+    // A URL for `path_and_query` that works without the token, for elements
+    // like `<img>` and `<video>` that can't send headers. The session token
+    // never ends up in URLs, logs or history.
+    pub fn issue_ticket(&self, path_and_query: &str) -> String {
+        let ticket = generate_token();
+        let separator = if path_and_query.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!("{path_and_query}{separator}ticket={ticket}");
+
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| ticket.last_used.elapsed() < TICKET_IDLE_TIMEOUT);
+        tickets.insert(
+            ticket,
+            Ticket {
+                url: url.clone(),
+                last_used: Instant::now(),
+            },
+        );
+
+        url
+    }
+
+    pub fn has_valid_ticket<B>(&self, req: &Request<B>) -> bool {
+        let Some(ticket) = query_param(req, "ticket") else {
+            return false;
+        };
+        let Some(url) = req.uri().path_and_query() else {
+            return false;
+        };
+
+        let mut tickets = self.tickets.lock().unwrap();
+
+        match tickets.get_mut(ticket) {
+            Some(issued)
+                if issued.url == url.as_str()
+                    && issued.last_used.elapsed() < TICKET_IDLE_TIMEOUT =>
+            {
+                issued.last_used = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn query_param<'a, B>(req: &'a Request<B>, name: &str) -> Option<&'a str> {
+    req.uri().query().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            pair.split_once('=')
+                .filter(|(key, _)| *key == name)
+                .map(|(_, value)| value)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(port: u16, allowed_origins: &[&str]) -> Auth {
+        Auth::new(&ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            port_fallback: false,
+            allowed_origins: allowed_origins
+                .iter()
+                .map(|origin| origin.to_string())
+                .collect(),
+        })
+    }
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder().uri(uri);
+
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[test]
+    fn generates_random_hex_tokens() {
+        let token = generate_token();
+
+        assert_eq!(token.len(), 64);
+        assert!(token.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn finds_query_params() {
+        let req = request("/fs.readFile?path=a&token=secret&x", &[]);
+
+        assert_eq!(query_param(&req, "token"), Some("secret"));
+        assert_eq!(query_param(&req, "path"), Some("a"));
+        assert_eq!(query_param(&req, "x"), None);
+        assert_eq!(query_param(&request("/fs.readFile", &[]), "token"), None);
+    }
+
+    #[test]
+    fn allows_only_local_hosts() {
+        let auth = auth(5369, &[]);
+
+        assert!(auth.is_allowed_host(&request("/", &[("Host", "localhost:5369")])));
+        assert!(auth.is_allowed_host(&request("/", &[("Host", "127.0.0.1:5369")])));
+        assert!(auth.is_allowed_host(&request("/", &[("Host", "[::1]:5369")])));
+        assert!(!auth.is_allowed_host(&request("/", &[("Host", "evil.com:5369")])));
+        assert!(!auth.is_allowed_host(&request("/", &[("Host", "localhost")])));
+        assert!(!auth.is_allowed_host(&request("/", &[])));
+    }
+
+    #[test]
+    fn allows_hosts_without_the_default_port() {
+        let auth = auth(80, &[]);
+
+        assert!(auth.is_allowed_host(&request("/", &[("Host", "localhost")])));
+        assert!(auth.is_allowed_host(&request("/", &[("Host", "localhost:80")])));
+    }
+
+    #[test]
+    fn checks_the_origin() {
+        let auth = auth(5369, &["https://app.example.com"]);
+        let check = |headers| auth.check_origin(&request("/", headers));
+
+        assert_eq!(
+            check(&[("Origin", "http://localhost:5369")]),
+            Ok(Some("http://localhost:5369".to_string()))
+        );
+        assert_eq!(
+            check(&[("Origin", "https://app.example.com")]),
+            Ok(Some("https://app.example.com".to_string()))
+        );
+        assert_eq!(check(&[("Origin", "https://evil.com")]), Err(()));
+        assert_eq!(check(&[("Origin", "null")]), Err(()));
+    }
+
+    #[test]
+    fn falls_back_to_the_referer() {
+        let auth = auth(5369, &[]);
+        let check = |headers| auth.check_origin(&request("/", headers));
+
+        assert_eq!(
+            check(&[("Referer", "http://localhost:5369/index.html?a=b")]),
+            Ok(Some("http://localhost:5369".to_string()))
+        );
+        assert_eq!(check(&[("Referer", "https://evil.com/page")]), Err(()));
+    }
+
+    #[test]
+    fn trusts_requests_without_an_origin_unless_cross_site() {
+        let auth = auth(5369, &[]);
+        let check = |headers| auth.check_origin(&request("/", headers));
+
+        assert_eq!(check(&[]), Ok(None));
+        assert_eq!(check(&[("Sec-Fetch-Site", "none")]), Ok(None));
+        assert_eq!(check(&[("Sec-Fetch-Site", "same-origin")]), Ok(None));
+        assert_eq!(check(&[("Sec-Fetch-Site", "cross-site")]), Err(()));
+        assert_eq!(check(&[("Sec-Fetch-Site", "same-site")]), Err(()));
+    }
+
+    #[test]
+    fn requires_an_exact_origin_header() {
+        let auth = auth(5369, &["https://app.example.com"]);
+        let allowed = |headers| auth.has_allowed_origin(&request("/", headers));
+
+        assert!(allowed(&[("Origin", "http://localhost:5369")]));
+        assert!(allowed(&[("Origin", "https://app.example.com")]));
+        assert!(!allowed(&[("Origin", "https://app.example.com.evil.com")]));
+        assert!(!allowed(&[("Referer", "http://localhost:5369/index.html")]));
+        assert!(!allowed(&[("Sec-Fetch-Site", "same-origin")]));
+        assert!(!allowed(&[]));
+    }
+
+    #[test]
+    fn checks_the_token() {
+        let auth = auth(5369, &[]);
+        let token = auth.token().to_string();
+
+        assert!(auth.has_valid_token(&request("/", &[(TOKEN_HEADER, &token)])));
+        assert!(!auth.has_valid_token(&request("/", &[(TOKEN_HEADER, "wrong")])));
+        assert!(!auth.has_valid_token(&request("/", &[])));
+
+        // Only the upgrade request takes it from the query
+        assert!(!auth.has_valid_token(&request(&format!("/?token={token}"), &[])));
+        assert!(auth.has_valid_query_token(&request(&format!("/ws?token={token}"), &[])));
+        assert!(!auth.has_valid_query_token(&request("/ws?token=wrong", &[])));
+    }
+
+    #[test]
+    fn tickets_open_only_the_issued_url() {
+        let auth = auth(5369, &[]);
+
+        let url = auth.issue_ticket("/fs.readFile?path=a.mp4");
+        assert!(url.starts_with("/fs.readFile?path=a.mp4&ticket="));
+        assert!(auth.has_valid_ticket(&request(&url, &[])));
+        assert!(auth.has_valid_ticket(&request(&url, &[])));
+
+        let ticket = url.rsplit('=').next().unwrap();
+        let other = format!("/fs.readFile?path=secret.txt&ticket={ticket}");
+        assert!(!auth.has_valid_ticket(&request(&other, &[])));
+        assert!(!auth.has_valid_ticket(&request("/fs.readFile?ticket=unknown", &[])));
+        assert!(!auth.has_valid_ticket(&request("/fs.readFile?path=a.mp4", &[])));
+
+        let url = auth.issue_ticket("/media");
+        assert!(url.starts_with("/media?ticket="));
+        assert!(auth.has_valid_ticket(&request(&url, &[])));
+    }
+
+    #[test]
+    fn tickets_expire_when_idle() {
+        let auth = auth(5369, &[]);
+        let url = auth.issue_ticket("/media");
+
+        for ticket in auth.tickets.lock().unwrap().values_mut() {
+            ticket.last_used -= TICKET_IDLE_TIMEOUT;
+        }
+
+        assert!(!auth.has_valid_ticket(&request(&url, &[])));
+    }
+}
@@ -19,13 +19,10 @@ pub fn search_chromium_based_browser() -> Option<&'static str> {
         "vivaldi",
     ];
 
-    for browser in BROWSER_LIST.iter() {
-        if which::which(browser).is_ok() {
-            return Some(browser);
-        }
-    }
-
-    None
+    BROWSER_LIST
+        .iter()
+        .find(|browser| which::which(browser).is_ok())
+        .copied()
 }
 
 pub fn open(url: &str) -> tokio::process::Child {
@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use http::Request;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use lenz_core::{
+    events::EventBus,
+    invoke::{InvokeHandlerInfo, InvokeRequest, InvokeResult},
+};
+use serde_json::json;
+
+use crate::{
+    app::App,
+    auth::{constant_time_eq, generate_token},
+    server::{full, Body},
+    state::invoke_handlers::json_to_form,
+};
+
+pub const PREFIX: &str = "_confirm/";
+pub const CONFIRMED_EVENT: &str = "app.confirmed";
+
+// Unanswered confirmations are denied after this long
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// A command a page asked to run, waiting for the user. The secret only
+// travels inside the confirmation page, which the asking page can't read.
+struct Confirmation {
+    secret: String,
+    command: String,
+    args: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Default)]
+pub struct Confirmations {
+    pending: Mutex<HashMap<String, Confirmation>>,
+}
+
+impl Confirmations {
+    // Returns the id of the confirmation, answered at `/_confirm/<id>`
+    pub fn request(
+        self: &Arc<Self>,
+        command: String,
+        args: serde_json::Map<String, serde_json::Value>,
+        events: EventBus,
+    ) -> String {
+        let id = generate_token();
+
+        self.pending.lock().unwrap().insert(
+            id.clone(),
+            Confirmation {
+                secret: generate_token(),
+                command,
+                args,
+            },
+        );
+
+        let confirmations = Arc::downgrade(self);
+        let expired = id.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(CONFIRM_TIMEOUT).await;
+
+            let removed = confirmations
+                .upgrade()
+                .and_then(|confirmations| confirmations.pending.lock().unwrap().remove(&expired));
+
+            if removed.is_some() {
+                events.emit(CONFIRMED_EVENT, json!({ "id": expired, "allowed": false }));
+            }
+        });
+
+        id
+    }
+
+    fn page(&self, id: &str) -> Option<(String, String, serde_json::Value)> {
+        self.pending.lock().unwrap().get(id).map(|confirmation| {
+            (
+                confirmation.secret.clone(),
+                confirmation.command.clone(),
+                json!(confirmation.args),
+            )
+        })
+    }
+
+    fn take(&self, id: &str, secret: &str) -> Option<Confirmation> {
+        let mut pending = self.pending.lock().unwrap();
+
+        match pending.get(id) {
+            Some(confirmation) if constant_time_eq(&confirmation.secret, secret) => {
+                pending.remove(id)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn header<'a>(req: &'a Request<Incoming>, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+// Only a top level page of the agent itself gets to see or answer a
+// confirmation. `fetch` and frames can't fake these headers.
+fn is_navigation(req: &Request<Incoming>) -> bool {
+    header(req, "Sec-Fetch-Dest") == Some("document")
+        && matches!(
+            header(req, "Sec-Fetch-Site"),
+            Some("same-origin") | Some("none")
+        )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn html(status: u16, title: &str, body: &str) -> Result<http::Response<Body>, Infallible> {
+    let page = format!(
+        "<!DOCTYPE html><html lang=\"pt-BR\"><head><meta charset=\"utf-8\"><title>{title}</title>\
+        <style>body{{font-family:sans-serif;margin:2rem;max-width:36rem}}\
+        pre{{background:#f3f3f3;padding:.75rem;overflow:auto}}\
+        button{{font-size:1rem;padding:.4rem 1rem;margin-right:.5rem}}</style></head>\
+        <body>{body}</body></html>"
+    );
+
+    Ok(http::Response::builder()
+        .status(status)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .header("Referrer-Policy", "no-referrer")
+        // Cuts the page off from the window that opened it
+        .header("Cross-Origin-Opener-Policy", "same-origin")
+        .header("X-Frame-Options", "DENY")
+        .header(
+            "Content-Security-Policy",
+            "default-src 'none'; style-src 'unsafe-inline'; form-action 'self'; frame-ancestors 'none'",
+        )
+        .body(full(page))
+        .unwrap())
+}
+
+fn not_found() -> Result<http::Response<Body>, Infallible> {
+    html(
+        404,
+        "Confirmação expirada",
+        "<h1>Confirmação expirada</h1><p>Esta solicitação já foi respondida ou expirou.</p>",
+    )
+}
+
+fn describe(info: Option<InvokeHandlerInfo>, command: &str, args: &serde_json::Value) -> String {
+    let description = info
+        .and_then(|info| info.description)
+        .unwrap_or_else(|| command.to_string());
+
+    format!(
+        "<p>Uma página do editor pediu para executar <strong>{}</strong>: {}</p><pre>{}</pre>",
+        escape(command),
+        escape(&description),
+        escape(&serde_json::to_string_pretty(args).unwrap_or_default())
+    )
+}
+
+pub async fn resolve_page(
+    req: Request<Incoming>,
+    app: App,
+) -> Result<http::Response<Body>, Infallible> {
+    if !is_navigation(&req) {
+        return html(403, "Proibido", "<h1>Proibido</h1>");
+    }
+
+    let id = &req.uri().path().trim_matches('/')[PREFIX.len()..];
+
+    let Some((secret, command, args)) = app.confirmations.page(id) else {
+        return not_found();
+    };
+
+    let info = app.invoke_handlers.read().await.catalog().get(&command);
+
+    html(
+        200,
+        "Confirmar ação",
+        &format!(
+            "<h1>Permitir esta ação?</h1>{}\
+            <form method=\"post\"><input type=\"hidden\" name=\"secret\" value=\"{}\">\
+            <button name=\"answer\" value=\"deny\">Negar</button>\
+            <button name=\"answer\" value=\"allow\">Permitir</button></form>",
+            describe(info, &command, &args),
+            escape(&secret)
+        ),
+    )
+}
+
+fn form_field(body: &str, name: &str) -> Option<String> {
+    body.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+
+        if key != name {
+            return None;
+        }
+
+        urlencoding::decode(&value.replace('+', " "))
+            .ok()
+            .map(|value| value.into_owned())
+    })
+}
+
+fn result_json(result: InvokeResult) -> serde_json::Value {
+    match result {
+        InvokeResult::Json(value) => json!({ "result": value }),
+        InvokeResult::Text(text) => json!({ "result": text }),
+        InvokeResult::Error(e) => json!({ "error": e }),
+        _ => json!({ "result": null }),
+    }
+}
+
+pub async fn resolve_answer(
+    req: Request<Incoming>,
+    app: App,
+) -> Result<http::Response<Body>, Infallible> {
+    if !is_navigation(&req) {
+        return html(403, "Proibido", "<h1>Proibido</h1>");
+    }
+
+    let id = req.uri().path().trim_matches('/')[PREFIX.len()..].to_string();
+
+    let body = match req.into_body().collect().await {
+        Ok(body) => String::from_utf8_lossy(&body.to_bytes()).into_owned(),
+        Err(_) => return html(400, "Requisição inválida", "<h1>Requisição inválida</h1>"),
+    };
+
+    let secret = form_field(&body, "secret").unwrap_or_default();
+
+    let Some(confirmation) = app.confirmations.take(&id, &secret) else {
+        return not_found();
+    };
+
+    if form_field(&body, "answer").as_deref() != Some("allow") {
+        app.events
+            .emit(CONFIRMED_EVENT, json!({ "id": id, "allowed": false }));
+
+        return html(
+            200,
+            "Ação negada",
+            "<h1>Ação negada</h1><p>Você já pode fechar esta janela.</p>",
+        );
+    }
+
+    let request = InvokeRequest::new(confirmation.command, json_to_form(confirmation.args));
+    let invoke = app.invoke_handlers.read().await.invoke_confirmed(request);
+    let result = result_json(invoke.await);
+
+    let page = match result.get("error") {
+        Some(error) => format!(
+            "<h1>A ação falhou</h1><p>{}</p>",
+            escape(error["message"].as_str().unwrap_or_default())
+        ),
+        None => "<h1>Ação permitida</h1><p>Você já pode fechar esta janela.</p>".to_string(),
+    };
+
+    let mut event = json!({ "id": id, "allowed": true });
+    event
+        .as_object_mut()
+        .unwrap()
+        .extend(result.as_object().cloned().unwrap_or_default());
+
+    app.events.emit(CONFIRMED_EVENT, event);
+
+    html(200, "Confirmar ação", &page)
+}
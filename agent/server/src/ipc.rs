@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use lenz_core::config::util::cli_positional_args;
+
+use crate::{app::App, server::open_browser};
+
+// Sent by a second instance to the running agent
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Handoff {
+    pub args: Vec<String>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Handoff {
+    pub fn from_env() -> Self {
+        Self {
+            args: std::env::args().skip(1).collect(),
+            // The running agent may have a different working directory
+            files: cli_positional_args()
+                .into_iter()
+                .map(|file| std::path::absolute(&file).unwrap_or_else(|_| file.into()))
+                .collect(),
+        }
+    }
+}
+
+async fn handle_handoff(app: &App, handoff: Handoff) {
+    let base_url = app.config.server.base_url();
+
+    // No editor window is connected to the channel, open a new one
+    if app.events.subscriber_count() == 0 {
+        let file = handoff.files.first().map(|file| file.to_string_lossy());
+        open_browser(&base_url, file.as_deref());
+        return;
+    }
+
+    if handoff.files.is_empty() {
+        app.events.emit(
+            "app.open",
+            serde_json::json!({ "file": null, "args": handoff.args }),
+        );
+    }
+
+    // Straight to the extensions, `app.open` can be emitted by any client
+    {
+        let extension_host = app.extension_host.read().await;
+
+        for file in &handoff.files {
+            extension_host.open(file);
+        }
+    }
+
+    for file in handoff.files {
+        app.events.emit(
+            "app.open",
+            serde_json::json!({ "file": file, "args": handoff.args }),
+        );
+    }
+}
+
+#[cfg(unix)]
+pub async fn forward(socket: &Path, handoff: &Handoff) -> std::io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let mut stream = tokio::net::UnixStream::connect(socket).await?;
+    let mut message = serde_json::to_vec(handoff)?;
+    message.push(b'\n');
+
+    stream.write_all(&message).await?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).await?;
+
+    if reply.trim() == "ok" {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "Unexpected reply from agent: {}",
+            reply.trim()
+        )))
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn forward(_socket: &Path, _handoff: &Handoff) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+pub type IpcListener = tokio::net::UnixListener;
+
+#[cfg(not(unix))]
+pub struct IpcListener;
+
+#[cfg(unix)]
+pub fn bind(socket: &Path) -> Option<IpcListener> {
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+
+    match tokio::net::UnixListener::bind(socket) {
+        Ok(listener) => Some(listener),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            // Another agent (started with a fallback port) owns the socket
+            if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+                return None;
+            }
+
+            // Left behind by an agent that didn't shut down cleanly
+            std::fs::remove_file(socket).ok();
+
+            tokio::net::UnixListener::bind(socket)
+                .inspect_err(|e| eprintln!("Failed to bind IPC socket {:?}: {}", socket, e))
+                .ok()
+        }
+        Err(e) => {
+            eprintln!("Failed to bind IPC socket {:?}: {}", socket, e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn bind(_socket: &Path) -> Option<IpcListener> {
+    None
+}
+
+#[cfg(unix)]
+pub async fn serve(listener: IpcListener, app: App) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("IPC accept error: {}", e);
+                continue;
+            }
+        };
+
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+
+            if let Err(e) = stream.read_line(&mut line).await {
+                eprintln!("Failed to read IPC message: {}", e);
+                return;
+            }
+
+            match serde_json::from_str::<Handoff>(&line) {
+                Ok(handoff) => {
+                    // Acknowledge first, the second instance only waits for delivery
+                    stream.get_mut().write_all(b"ok\n").await.ok();
+                    handle_handoff(&app, handoff).await;
+                }
+                Err(e) => {
+                    eprintln!("Invalid IPC message: {}", e);
+                    stream.get_mut().write_all(b"error\n").await.ok();
+                }
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_listener: IpcListener, _app: App) {}
@@ -1,17 +1,29 @@
 use app::AppState;
+use lenz_core::config::AgentConfig;
 
 mod app;
+mod auth;
 mod browser;
+mod confirm;
+mod ipc;
 mod server;
 mod state;
+mod temp;
+mod websocket;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let app = AppState::new();
+    let mut config = AgentConfig::load();
+
+    let Some(listener) = server::bind(&mut config).await else {
+        return Ok(());
+    };
+
+    let app = AppState::new(config);
 
     state::extensions::init(app.clone()).await;
 
-    server::start(app.clone()).await?;
+    server::start(app.clone(), listener).await?;
 
     state::extensions::shutdown(app.clone()).await;
 
@@ -10,5 +10,8 @@
 
     document.currentScript.after(importmap);
 
+    window.__LENZ_BASE_URL__ = $BASE_URL$;
+    // Páginas servidas pelo agente já recebem o token, veja `inject_token`
+    window.__LENZ_TOKEN__ ??= $TOKEN$;
     window.__LENZ_EXTENSIONS__ = $EXTENSIONS$;
 })()
\ No newline at end of file
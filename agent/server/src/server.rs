@@ -1,17 +1,19 @@
 use bytes::Bytes;
+use futures_util::TryStreamExt;
 use http::{Method, Request};
-use http_body_util::Full;
-use hyper::body::Incoming;
-use lenz_core::config::consts::{ADDR, BASE_URL};
-use lenz_core::invoke::InvokeResult;
-use mime_guess::mime::{APPLICATION_JSON, APPLICATION_OCTET_STREAM, TEXT_PLAIN_UTF_8};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use lenz_core::config::{AgentConfig, ServerConfig};
+use lenz_core::invoke::{InvokeError, InvokeErrorCode, InvokeRequest, InvokeResult, InvokeStream};
+use mime_guess::mime::{APPLICATION_JSON, APPLICATION_OCTET_STREAM, TEXT_HTML, TEXT_PLAIN_UTF_8};
 use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
 
 use crate::app::App;
-use crate::state::invoke_handlers::get_invoke_request;
+use crate::state::invoke_handlers::{get_invoke_request, query_invoke_request};
+use crate::{auth, confirm, ipc, websocket};
 use std::pin::pin;
 
 async fn countdown(message: &str, seconds: u32) {
@@ -22,50 +24,125 @@ async fn countdown(message: &str, seconds: u32) {
     }
 }
 
-pub fn open_browser() {
-    let url = match std::env::args().nth(1) {
+pub fn open_browser(base_url: &str, file: Option<&str>) {
+    let url = match file {
         // Encode URI component arg
-        Some(arg) => &format!("{}?file={}", BASE_URL, urlencoding::encode(&arg)),
-        None => BASE_URL,
+        Some(arg) => format!("{}?file={}", base_url, urlencoding::encode(arg)),
+        None => base_url.to_string(),
     };
 
-    crate::browser::open_in_app_mode(url);
+    crate::browser::open_in_app_mode(&url);
 }
 
-pub fn create_response() -> http::response::Builder {
-    let response = http::Response::builder().header("Access-Control-Allow-Origin", "*");
+pub type Body = UnsyncBoxBody<Bytes, std::io::Error>;
+
+pub type QuitSignal = Arc<tokio::sync::RwLock<Option<tokio::sync::mpsc::Sender<()>>>>;
 
-    return response.header("Access-Control-Expose-Headers", "X-Invoke-Result");
+const INVOKE_PREFIX: &str = "_invoke/";
+
+pub fn full<T: Into<Bytes>>(chunk: T) -> Body {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed_unsync()
 }
 
-pub async fn start(app: App) -> Result<(), Box<dyn std::error::Error>> {
-    // Hide cursor
-    eprint!("\x1b[?25l");
-    
-    let listener = match TcpListener::bind(ADDR).await {
+pub fn stream(stream: InvokeStream) -> Body {
+    StreamBody::new(stream.into_bytes().map_ok(Frame::data)).boxed_unsync()
+}
+
+pub fn create_response() -> http::response::Builder {
+    http::Response::builder().header(
+        "Access-Control-Expose-Headers",
+        "X-Invoke-Result, X-Invoke-Stream, X-Invoke-Meta, Content-Range",
+    )
+}
+
+// Binds the configured address, updating `config` with the port actually in
+// use. Returns None when the server can't (or shouldn't) be started.
+pub async fn bind(config: &mut AgentConfig) -> Option<TcpListener> {
+    let ipc_socket = config.ipc_socket.clone();
+    let config = &mut config.server;
+
+    let listener = match TcpListener::bind(config.addr()).await {
         Ok(listener) => listener,
         Err(e) => match e.kind() {
+            std::io::ErrorKind::AddrInUse if config.port_fallback => {
+                eprintln!(
+                    "\x1b[33mPorta {} já está em uso, procurando uma porta livre...\x1b[0m",
+                    config.port
+                );
+
+                let fallback = ServerConfig {
+                    port: 0,
+                    ..config.clone()
+                };
+
+                match TcpListener::bind(fallback.addr()).await {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        eprintln!("Erro ao iniciar servidor: {}", e);
+                        return None;
+                    }
+                }
+            }
             std::io::ErrorKind::AddrInUse => {
-                eprintln!("\x1b[31;1mPorta {} já está em uso.\x1b[0m", ADDR);
-                open_browser();
-                return Ok(());
+                let handoff = ipc::Handoff::from_env();
+
+                match ipc::forward(&ipc_socket, &handoff).await {
+                    Ok(()) => println!("Argumentos enviados para a instância em execução."),
+                    Err(e) => {
+                        eprintln!("\x1b[31;1mPorta {} já está em uso.\x1b[0m", config.port);
+                        eprintln!("Não foi possível contatar a instância em execução: {}", e);
+                        let file = handoff.files.first().map(|file| file.to_string_lossy());
+                        open_browser(&config.base_url(), file.as_deref());
+                    }
+                }
+
+                return None;
             }
             _ => {
                 eprintln!("Erro ao iniciar servidor: {}", e);
-                return Ok(());
+                return None;
             }
         },
     };
 
-    println!("\x1b[32;1mServidor iniciado em http://{}\x1b[0m", ADDR);
+    match listener.local_addr() {
+        Ok(addr) => config.port = addr.port(),
+        Err(e) => {
+            eprintln!("Erro ao iniciar servidor: {}", e);
+            return None;
+        }
+    }
+
+    Some(listener)
+}
+
+pub async fn start(app: App, listener: TcpListener) -> Result<(), Box<dyn std::error::Error>> {
+    // Hide cursor
+    eprint!("\x1b[?25l");
+
+    let base_url = app.config.server.base_url();
+
+    println!("\x1b[32;1mServidor iniciado em {}\x1b[0m", base_url);
 
     #[cfg(not(debug_assertions))]
     {
         if std::env::args().all(|arg| arg != "--no-browser") {
-            open_browser()
+            let file = lenz_core::config::util::cli_positional_args()
+                .into_iter()
+                .next();
+            open_browser(&base_url, file.as_deref())
         }
     }
 
+    let ipc_listener = ipc::bind(&app.config.ipc_socket);
+    let owns_ipc_socket = ipc_listener.is_some();
+
+    if let Some(ipc_listener) = ipc_listener {
+        tokio::spawn(ipc::serve(ipc_listener, app.clone()));
+    }
+
     let server = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
     let graceful = hyper_util::server::graceful::GracefulShutdown::new();
     let mut ctrl_c = pin!(tokio::signal::ctrl_c());
@@ -118,6 +195,10 @@ pub async fn start(app: App) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if owns_ipc_socket {
+        std::fs::remove_file(&app.config.ipc_socket).ok();
+    }
+
     // Show cursor
     eprint!("\x1b[?25h");
 
@@ -127,30 +208,121 @@ pub async fn start(app: App) -> Result<(), Box<dyn std::error::Error>> {
 async fn handle_request(
     req: Request<Incoming>,
     app: App,
-    quit_signal: Arc<tokio::sync::RwLock<Option<tokio::sync::mpsc::Sender<()>>>>,
-) -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
-    match *req.method() {
+    quit_signal: QuitSignal,
+) -> Result<http::Response<Body>, Infallible> {
+    if !app.auth.is_allowed_host(&req) {
+        return forbidden();
+    }
+
+    let origin = match app.auth.check_origin(&req) {
+        Ok(origin) => origin,
+        Err(()) => return forbidden(),
+    };
+
+    let authorized = match *req.method() {
         Method::GET => match req.uri().path().trim_matches('/') {
-            "importmap.json" => resolve_importmap(req, app).await,
-            "lenz-init.js" => resolve_init_script(req, app).await,
-            _ => resolve_static(req, app).await,
+            "lenz-channel" => {
+                app.auth.has_valid_token(&req)
+                    || (websocket::is_upgrade_request(&req) && app.auth.has_valid_query_token(&req))
+            }
+            "_commands" => app.auth.has_valid_token(&req),
+            // The confirmation page checks the request itself, see `confirm`
+            path if path.starts_with(confirm::PREFIX) => true,
+            path if path.starts_with(INVOKE_PREFIX) => {
+                app.auth.has_valid_token(&req) || app.auth.has_valid_ticket(&req)
+            }
+            _ => true,
         },
-        Method::POST => resolve_invoke(req, app, quit_signal).await,
-        _ => method_not_allowed(),
-    }
+        Method::POST => {
+            req.uri()
+                .path()
+                .trim_matches('/')
+                .starts_with(confirm::PREFIX)
+                || app.auth.has_valid_token(&req)
+        }
+        _ => true,
+    };
+
+    let response = if !authorized {
+        unauthorized()
+    } else {
+        match *req.method() {
+            Method::GET => match req.uri().path().trim_matches('/') {
+                "lenz-channel" if websocket::is_upgrade_request(&req) => {
+                    websocket::resolve_websocket(req, app, quit_signal).await
+                }
+                "importmap.json" => resolve_importmap(req, app).await,
+                "lenz-init.js" => resolve_init_script(req, app).await,
+                "_commands" => resolve_commands(req, app).await,
+                path if path.starts_with(INVOKE_PREFIX) => {
+                    resolve_query_invoke(req, app, quit_signal).await
+                }
+                path if path.starts_with(confirm::PREFIX) => confirm::resolve_page(req, app).await,
+                _ => resolve_static(req, app).await,
+            },
+            Method::POST => match req.uri().path().trim_matches('/') {
+                path if path.starts_with(confirm::PREFIX) => {
+                    confirm::resolve_answer(req, app).await
+                }
+                _ => resolve_invoke(req, app, quit_signal).await,
+            },
+            Method::OPTIONS => preflight(),
+            _ => method_not_allowed(),
+        }
+    };
+
+    // Only origins on the allow-list get CORS access, never a wildcard
+    response.map(|mut response| {
+        if let Some(origin) = origin.and_then(|origin| origin.parse().ok()) {
+            let headers = response.headers_mut();
+            headers.insert("Access-Control-Allow-Origin", origin);
+            headers.insert("Vary", http::HeaderValue::from_static("Origin"));
+        }
+
+        response
+    })
+}
+
+fn forbidden() -> Result<http::Response<Body>, Infallible> {
+    Ok(http::Response::builder()
+        .status(403)
+        .body(full("Forbidden"))
+        .unwrap())
+}
+
+fn unauthorized() -> Result<http::Response<Body>, Infallible> {
+    let error = InvokeError::new(InvokeErrorCode::Unauthorized, "Missing or invalid token");
+
+    Ok(create_response()
+        .status(error.http_status())
+        .header("Content-Type", APPLICATION_JSON.to_string())
+        .body(full(serde_json::to_string(&error).unwrap()))
+        .unwrap())
 }
 
-fn method_not_allowed() -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
+fn preflight() -> Result<http::Response<Body>, Infallible> {
+    Ok(create_response()
+        .status(204)
+        .header("Access-Control-Allow-Methods", "GET, POST")
+        .header(
+            "Access-Control-Allow-Headers",
+            format!("Content-Type, {}", auth::TOKEN_HEADER),
+        )
+        .body(full(""))
+        .unwrap())
+}
+
+fn method_not_allowed() -> Result<http::Response<Body>, Infallible> {
     Ok(http::Response::builder()
         .status(405)
-        .body("Method Not Allowed".into())
+        .body(full("Method Not Allowed"))
         .unwrap())
 }
 
 async fn resolve_static(
     req: Request<Incoming>,
     app: App,
-) -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
+) -> Result<http::Response<Body>, Infallible> {
     let path = req.uri().path();
     let file_path = app.static_files.read().await.resolve(path);
 
@@ -160,68 +332,192 @@ async fn resolve_static(
         match tokio::fs::read(&file_path).await {
             Ok(file) => {
                 let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+
+                let file = if mime == TEXT_HTML {
+                    inject_token(file, app.auth.token())
+                } else {
+                    file
+                };
+
                 Ok(response
                     .header("Content-Type", mime.to_string())
-                    .body(http_body_util::Full::<Bytes>::from(file))
+                    .body(full(file))
                     .unwrap())
             }
             Err(e) => {
                 eprintln!("Error reading file: {}", e);
                 Ok(response
                     .status(500)
-                    .body("Internal Server Error".into())
+                    .body(full("Internal Server Error"))
                     .unwrap())
             }
         }
     } else {
-        Ok(response.status(404).body("Not Found".into()).unwrap())
+        Ok(response.status(404).body(full("Not Found")).unwrap())
     }
 }
 
+// Other sites can't read pages of the agent, unlike scripts they can load
+// with `<script src>`
+fn inject_token(html: Vec<u8>, token: &str) -> Vec<u8> {
+    let script = format!(
+        "<script>window.__LENZ_TOKEN__ = {};</script>",
+        serde_json::to_string(token).unwrap()
+    );
+
+    let head = html
+        .windows(6)
+        .position(|tag| tag.eq_ignore_ascii_case(b"<head>"))
+        .map(|position| position + 6)
+        .unwrap_or(0);
+
+    [&html[..head], script.as_bytes(), &html[head..]].concat()
+}
+
 async fn resolve_invoke(
     req: Request<Incoming>,
     app: App,
-    quit_signal: Arc<tokio::sync::RwLock<Option<tokio::sync::mpsc::Sender<()>>>>,
-) -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
-    let request = match get_invoke_request(req).await {
+    quit_signal: QuitSignal,
+) -> Result<http::Response<Body>, Infallible> {
+    let request = match get_invoke_request(req, &app.config.temp_dir).await {
         Some(request) => request,
         None => {
             return Ok(create_response()
                 .status(400)
-                .body("Bad Request".into())
+                .body(full("Bad Request"))
                 .unwrap())
         }
     };
-    let result = app.invoke_handlers.read().await.invoke(request).await;
 
-    let response = create_response()
+    invoke_response(request, app, quit_signal).await
+}
+
+// `GET /_invoke/<command>?arg=value`, for URLs like `<video src>` that can't
+// send a body. Only readonly commands can be invoked this way.
+async fn resolve_query_invoke(
+    req: Request<Incoming>,
+    app: App,
+    quit_signal: QuitSignal,
+) -> Result<http::Response<Body>, Infallible> {
+    let command = req.uri().path().trim_matches('/')[INVOKE_PREFIX.len()..].to_string();
+
+    let readonly = app
+        .invoke_handlers
+        .read()
+        .await
+        .catalog()
+        .get(&command)
+        .is_some_and(|info| info.readonly);
+
+    if !readonly {
+        return method_not_allowed();
+    }
+
+    match query_invoke_request(&req, command) {
+        Some(request) => invoke_response(request, app, quit_signal).await,
+        None => Ok(create_response()
+            .status(400)
+            .body(full("Bad Request"))
+            .unwrap()),
+    }
+}
+
+// Header values must be ASCII, JSON can escape everything else
+fn ascii_json(value: &serde_json::Value) -> String {
+    let mut json = String::new();
+
+    for char in serde_json::to_string(value).unwrap().chars() {
+        if char.is_ascii() {
+            json.push(char);
+        } else {
+            for unit in char.encode_utf16(&mut [0; 2]) {
+                json.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+
+    json
+}
+
+async fn invoke_response(
+    request: InvokeRequest,
+    app: App,
+    quit_signal: QuitSignal,
+) -> Result<http::Response<Body>, Infallible> {
+    // hyper drops this future when the client goes away, the guard then
+    // cancels the request. The handler runs in its own task so it keeps
+    // going until it notices the cancellation.
+    let guard = request.cancellation.clone().drop_guard();
+
+    let invoke = app.invoke_handlers.read().await.invoke(request);
+    let task = tokio::spawn(invoke);
+
+    let result = match task.await {
+        Ok(result) => result,
+        Err(e) => InvokeError::internal(format!("Invoke task failed: {}", e)).into(),
+    };
+
+    guard.disarm();
+
+    let (result, meta) = result.into_parts();
+
+    let mut response = create_response()
         .status(200)
         .header("X-Invoke-Result", result.label());
 
+    if let Some(meta) = meta {
+        response = response.header("X-Invoke-Meta", ascii_json(&meta));
+    }
+
     match result {
-        InvokeResult::Void => Ok(response.body("".into()).unwrap()),
+        InvokeResult::Void => Ok(response.body(full("")).unwrap()),
         InvokeResult::Text(text) => Ok(response
             .header("Content-Type", TEXT_PLAIN_UTF_8.to_string())
-            .body(text.into())
+            .body(full(text))
             .unwrap()),
         InvokeResult::Json(json) => Ok(response
             .header("Content-Type", APPLICATION_JSON.to_string())
-            .body(serde_json::to_string(&json).unwrap().into())
+            .body(full(serde_json::to_string(&json).unwrap()))
             .unwrap()),
         InvokeResult::Binary(bytes) => Ok(response
             .header("Content-Type", APPLICATION_OCTET_STREAM.to_string())
-            .body(bytes.into())
+            .body(full(bytes))
             .unwrap()),
-        InvokeResult::Error(message) => Ok(response
-            .header("Content-Type", TEXT_PLAIN_UTF_8.to_string())
-            .body(message.into())
+        InvokeResult::Stream(invoke_stream) => {
+            let content_type = match invoke_stream {
+                InvokeStream::Bytes(_) | InvokeStream::Reader(_) => {
+                    APPLICATION_OCTET_STREAM.to_string()
+                }
+                InvokeStream::JsonLines(_) => "application/x-ndjson".to_string(),
+            };
+
+            Ok(response
+                .header("Content-Type", content_type)
+                .header("X-Invoke-Stream", invoke_stream.label())
+                .body(stream(invoke_stream))
+                .unwrap())
+        }
+        InvokeResult::Partial(range, invoke_stream) => Ok(response
+            .status(206)
+            .header("Content-Type", APPLICATION_OCTET_STREAM.to_string())
+            .header("Content-Range", range.content_range())
+            .header("Content-Length", range.len())
+            .header("Accept-Ranges", "bytes")
+            .header("X-Invoke-Stream", invoke_stream.label())
+            .body(stream(invoke_stream))
+            .unwrap()),
+        InvokeResult::Error(error) => Ok(response
+            .status(error.http_status())
+            .header("Content-Type", APPLICATION_JSON.to_string())
+            .body(full(serde_json::to_string(&error).unwrap()))
             .unwrap()),
+        InvokeResult::WithMeta(..) => unreachable!("taken apart above"),
         InvokeResult::Quit => {
             if let Some(quit_signal) = quit_signal.write().await.take() {
                 quit_signal.send(()).await.ok();
             }
 
-            Ok(response.body("".into()).unwrap())
+            Ok(response.body(full("")).unwrap())
         }
     }
 }
@@ -229,31 +525,49 @@ async fn resolve_invoke(
 async fn resolve_importmap(
     _req: Request<Incoming>,
     app: App,
-) -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
+) -> Result<http::Response<Body>, Infallible> {
     let importmap = app.get_importmap().await;
     // Not implemented
     Ok(create_response()
         .status(200)
         .header("Content-Type", "application/json")
-        .body(Full::new(Bytes::from(
-            serde_json::to_string_pretty(&importmap).unwrap(),
-        )))
+        .body(full(serde_json::to_string_pretty(&importmap).unwrap()))
         .unwrap())
 }
 
-async fn resolve_init_script(
+async fn resolve_commands(
     _req: Request<Incoming>,
     app: App,
-) -> Result<http::Response<http_body_util::Full<Bytes>>, Infallible> {
+) -> Result<http::Response<Body>, Infallible> {
+    let commands = app.invoke_handlers.read().await.catalog().list();
+
+    Ok(create_response()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(full(serde_json::to_string_pretty(&commands).unwrap()))
+        .unwrap())
+}
+
+// Pages served by the agent get the token inlined, see `resolve_static`.
+// Others, like the Vite dev server, only get it through this script when
+// they load it in CORS mode from an allowed origin.
+async fn resolve_init_script(
+    req: Request<Incoming>,
+    app: App,
+) -> Result<http::Response<Body>, Infallible> {
     let importmap = app.get_importmap().await;
     let extensions = app.extension_host.read().await.get_extensions_json().await;
+    let base_url = app.config.server.base_url();
+    let token = app.auth.has_allowed_origin(&req).then(|| app.auth.token());
 
     Ok(create_response()
         .header("Content-Type", "application/javascript")
-        .body(Full::new(Bytes::from(
+        .body(full(
             include_str!("./scripts/init.js")
                 .replace("$IMPORTS$", &serde_json::to_string(&importmap).unwrap())
-                .replace("$EXTENSIONS$", &serde_json::to_string(&extensions).unwrap()),
-        )))
+                .replace("$EXTENSIONS$", &serde_json::to_string(&extensions).unwrap())
+                .replace("$BASE_URL$", &serde_json::to_string(&base_url).unwrap())
+                .replace("$TOKEN$", &serde_json::to_string(&token).unwrap()),
+        ))
         .unwrap())
 }
@@ -0,0 +1,252 @@
+use std::{collections::HashSet, future::Future, pin::Pin, sync::Arc};
+
+use lenz_core::invoke::{
+    InvokeError, InvokeErrorCode, InvokeHandlerInfo, InvokeRequest, InvokeResult,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::app::App;
+
+pub const CHANGED_EVENT: &str = "extensions.changed";
+
+#[derive(Deserialize)]
+struct IdArgs {
+    id: String,
+}
+
+// The handlers live inside the app state, a weak reference avoids a cycle
+fn command<F, Fut>(
+    app: &App,
+    handler: F,
+) -> impl Fn(InvokeRequest) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
+where
+    F: Fn(App, String) -> Fut + 'static + Send + Sync,
+    Fut: Future<Output = Result<serde_json::Value, InvokeError>> + 'static + Send + Sync,
+{
+    let app = Arc::downgrade(app);
+    let handler = Arc::new(handler);
+
+    move |invoke| {
+        let app = app.upgrade();
+        let handler = handler.clone();
+
+        Box::pin(async move {
+            let Some(app) = app else {
+                return shutting_down().into();
+            };
+
+            match invoke.args.deserialize::<IdArgs>() {
+                Ok(IdArgs { id }) => handler(app, id).await.into(),
+                Err(e) => InvokeResult::Error(e.into()),
+            }
+        })
+    }
+}
+
+fn shutting_down() -> InvokeError {
+    InvokeError::new(InvokeErrorCode::Unavailable, "The agent is shutting down")
+}
+
+fn not_found(id: &str) -> InvokeError {
+    InvokeError::not_found(format!("Extension not found: {}", id)).with_details(json!({ "id": id }))
+}
+
+fn still_unloading(id: &str, action: &str) -> InvokeError {
+    InvokeError::conflict(format!(
+        "Extension {} is still unloading, {} it again once its invokes finish",
+        id, action
+    ))
+    .with_details(json!({ "id": id }))
+}
+
+// Installed extensions, including the disabled ones, with their status:
+// active, failed, unloading, disabled or inactive (found after the agent
+// started)
+async fn list(app: App) -> serde_json::Value {
+    let extension_host = app.extension_host.read().await;
+    let mut seen = HashSet::new();
+
+    let extensions = extension_host
+        .search_extensions()
+        .filter(|extension| seen.insert(extension.id()))
+        .map(|extension| match extension_host.get(&extension.id()) {
+            Some(active) => active.as_json(),
+            None => {
+                let mut json = extension.as_json();
+                json["status"] = if extension_host.is_enabled(&extension.id()) {
+                    json!("inactive")
+                } else {
+                    json!("disabled")
+                };
+                json
+            }
+        })
+        .collect::<Vec<_>>();
+
+    json!(extensions)
+}
+
+// The extension as listed by `extensions.list`, for extensions that aren't
+// active it is looked up on disk
+pub async fn status(app: &App, id: &str) -> serde_json::Value {
+    let extension_host = app.extension_host.read().await;
+
+    match extension_host.get(id) {
+        Some(extension) => extension.as_json(),
+        None => {
+            let mut json = extension_host
+                .find(id)
+                .map(|extension| extension.as_json())
+                .unwrap_or_else(|| json!({ "id": id }));
+            json["status"] = json!("disabled");
+            json
+        }
+    }
+}
+
+// Tells the UI the extension changed, returning its new status
+pub async fn announce(app: &App, id: &str) -> serde_json::Value {
+    let extension = status(app, id).await;
+
+    app.events
+        .emit(CHANGED_EVENT, json!({ "id": id, "extension": extension }));
+
+    extension
+}
+
+async fn enable(app: App, id: String) -> Result<serde_json::Value, InvokeError> {
+    let extension = {
+        let mut extension_host = app.extension_host.write().await;
+
+        if extension_host.is_unloading(&id) {
+            return Err(still_unloading(&id, "enable"));
+        }
+
+        if extension_host.has(&id) {
+            extension_host.set_enabled(&id, true)?;
+            None
+        } else {
+            let extension = extension_host.find(&id).ok_or_else(|| not_found(&id))?;
+            extension_host.set_enabled(&id, true)?;
+            Some(extension)
+        }
+    };
+
+    if let Some(extension) = extension {
+        extension.activate(app.clone()).await;
+    }
+
+    Ok(announce(&app, &id).await)
+}
+
+async fn disable(app: App, id: String) -> Result<serde_json::Value, InvokeError> {
+    let extension = {
+        let mut extension_host = app.extension_host.write().await;
+
+        if !extension_host.has(&id) && extension_host.find(&id).is_none() {
+            return Err(not_found(&id));
+        }
+
+        extension_host.set_enabled(&id, false)?;
+        extension_host.remove(&id)
+    };
+
+    if let Some(extension) = extension {
+        extension.deactivate(app.clone()).await;
+    }
+
+    Ok(announce(&app, &id).await)
+}
+
+// Deactivates the extension and activates it again as found on disk, with a
+// fresh manifest and library
+pub async fn restart(app: &App, id: &str) -> Result<(), InvokeError> {
+    let (current, fresh) = {
+        let mut extension_host = app.extension_host.write().await;
+
+        if !extension_host.is_enabled(id) {
+            return Err(
+                InvokeError::conflict(format!("Extension {} is disabled", id))
+                    .with_details(json!({ "id": id })),
+            );
+        }
+
+        if extension_host.is_unloading(id) {
+            return Err(still_unloading(id, "reload"));
+        }
+
+        let fresh = extension_host.find(id).ok_or_else(|| not_found(id))?;
+
+        (extension_host.remove(id), fresh)
+    };
+
+    // Loading the library again while the old one still runs would leave two
+    // copies of the extension
+    if let Some(current) = current {
+        if !current.deactivate(app.clone()).await {
+            return Err(still_unloading(id, "reload"));
+        }
+    }
+
+    fresh.activate(app.clone()).await;
+
+    Ok(())
+}
+
+async fn reload(app: App, id: String) -> Result<serde_json::Value, InvokeError> {
+    restart(&app, &id).await?;
+
+    Ok(announce(&app, &id).await)
+}
+
+pub async fn register(app: &App) {
+    let mut invoke_handlers = app.invoke_handlers.write().await;
+
+    let weak = Arc::downgrade(app);
+
+    invoke_handlers.add("extensions.list", move |_| {
+        let app = weak.upgrade();
+
+        Box::pin(async move {
+            match app {
+                Some(app) => list(app).await.into(),
+                None => shutting_down().into(),
+            }
+        })
+    });
+
+    invoke_handlers.add("extensions.enable", command(app, enable));
+    invoke_handlers.add("extensions.disable", command(app, disable));
+    invoke_handlers.add("extensions.reload", command(app, reload));
+
+    let id_args = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string" }
+        },
+        "required": ["id"]
+    });
+
+    invoke_handlers.describe(
+        InvokeHandlerInfo::new("extensions.list")
+            .with_description("Lists the installed extensions and their status")
+            .with_result(json!({ "type": "array", "items": { "type": "object" } }))
+            .with_readonly(),
+    );
+    invoke_handlers.describe(
+        InvokeHandlerInfo::new("extensions.enable")
+            .with_description("Enables and activates an extension")
+            .with_args(id_args.clone()),
+    );
+    invoke_handlers.describe(
+        InvokeHandlerInfo::new("extensions.disable")
+            .with_description("Deactivates an extension and keeps it disabled")
+            .with_args(id_args.clone()),
+    );
+    invoke_handlers.describe(
+        InvokeHandlerInfo::new("extensions.reload")
+            .with_description("Deactivates an extension and activates it again from disk")
+            .with_args(id_args),
+    );
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use lenz_core::extensions::manifest::{ExtensionError, ExtensionManifest};
+use notify::{RecursiveMode, Watcher};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use super::commands;
+use crate::app::{dynlib_path, App};
+
+pub const RELOADED_EVENT: &str = "extensions.reloaded";
+
+// Editors and compilers write files in several steps, changes are collected
+// until the files stay untouched for this long
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+// The dynamic loader hands back the library it already has for a path, each
+// activation loads its own copy so a rebuilt library is picked up
+pub fn copy_dynlib(temp_dir: &Path, id: &str, lib_path: &Path) -> Result<PathBuf, ExtensionError> {
+    let dir = temp_dir.join("dynlib");
+    let name = format!(
+        "{}-{}-{}-{}",
+        id,
+        std::process::id(),
+        COPIES.fetch_add(1, Ordering::Relaxed),
+        lib_path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let copy = dir.join(name);
+
+    crate::temp::prepare(temp_dir)
+        .and_then(|_| crate::temp::prepare(&dir))
+        .and_then(|_| {
+            let mut file = crate::temp::create_file(&copy)?;
+            std::io::copy(&mut std::fs::File::open(lib_path)?, &mut file)
+        })
+        .map_err(|e| {
+            ExtensionError::FailedToLoadDynlib(format!("{}: {}", lib_path.display(), e))
+        })?;
+
+    Ok(copy)
+}
+
+// Copies left by agents that exited with a library still in use, the ones of
+// this process carry its id
+pub fn remove_stale_copies(temp_dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(temp_dir.join("dynlib")) else {
+        return;
+    };
+
+    let own = format!("-{}-", std::process::id());
+
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().contains(&own) {
+            std::fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+// Only the files the agent itself loads, the rest is served as is
+fn is_reloaded_by(dir: &Path, path: &Path) -> bool {
+    if path == dir || path == dir.join("manifest.json") || path.starts_with(dir.join("esm")) {
+        return true;
+    }
+
+    let Ok(manifest) = ExtensionManifest::from_path(dir) else {
+        return false;
+    };
+
+    let is_dynlib = manifest
+        .dynlib
+        .map(|lib| dynlib_path(dir.join(lib)) == path)
+        .unwrap_or(false);
+    let is_main = manifest
+        .main
+        .map(|main| dir.join(main) == path)
+        .unwrap_or(false);
+
+    is_dynlib || is_main
+}
+
+// Extension directories with changes, the ones right under a search path
+fn changed_extensions(search_paths: &[PathBuf], paths: BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let search_path = search_paths.iter().find(|dir| path.starts_with(dir))?;
+            let name = path.strip_prefix(search_path).ok()?.components().next()?;
+            let dir = search_path.join(name);
+
+            is_reloaded_by(&dir, &path).then_some(dir)
+        })
+        .collect()
+}
+
+async fn reload(app: &App, dir: &Path) {
+    let active = app
+        .extension_host
+        .read()
+        .await
+        .extensions
+        .values()
+        .find(|extension| extension.dir() == dir)
+        .map(|extension| extension.id());
+    let id = ExtensionManifest::from_path(dir).map(|manifest| manifest.id);
+
+    // Gone from disk or renamed, the old one is not coming back
+    if let Some(active) = active.filter(|active| id.as_ref().ok() != Some(active)) {
+        let extension = app.extension_host.write().await.remove(&active);
+
+        if let Some(extension) = extension {
+            println!("Extension {} removed", active);
+
+            extension.deactivate(app.clone()).await;
+            app.events
+                .emit(RELOADED_EVENT, json!({ "id": active, "extension": null }));
+        }
+    }
+
+    let Ok(id) = id else {
+        return;
+    };
+
+    if !app.extension_host.read().await.is_enabled(&id) {
+        return;
+    }
+
+    println!("Reloading extension {}", id);
+
+    if let Err(e) = commands::restart(app, &id).await {
+        println!("Failed to reload extension {}: {}", id, e.message);
+        return;
+    }
+
+    let extension = commands::status(app, &id).await;
+
+    app.events
+        .emit(RELOADED_EVENT, json!({ "id": id, "extension": extension }));
+}
+
+// Watches the extension search paths and reloads the extensions whose
+// manifest, ESM files or library change
+pub fn watch(app: &App) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() {
+                for path in event.paths {
+                    tx.send(path).ok();
+                }
+            }
+        }
+    });
+
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            println!("Failed to watch extensions: {}", e);
+            return;
+        }
+    };
+
+    let search_paths = app.config.extensions_search_paths.clone();
+
+    for path in &search_paths {
+        if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
+            println!("Failed to watch {}: {}", path.display(), e);
+        }
+    }
+
+    let app = Arc::downgrade(app);
+
+    tokio::spawn(async move {
+        // Events stop once the watcher is dropped
+        let _watcher = watcher;
+
+        while let Some(path) = rx.recv().await {
+            let mut paths = BTreeSet::from([path]);
+
+            loop {
+                match tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                    Ok(Some(path)) => {
+                        paths.insert(path);
+                    }
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+
+            let Some(app) = app.upgrade() else {
+                return;
+            };
+
+            for dir in changed_extensions(&search_paths, paths) {
+                reload(&app, &dir).await;
+            }
+        }
+    });
+}
@@ -1,63 +1,108 @@
 use lenz_core::{
-    config::consts::BASE_URL,
+    events::EventBus,
+    invoke::InvokeHandlerInfo,
     extensions::{
         manifest::{ExtensionError, ExtensionManifest},
-        plugin::{LenzPlugin, LenzPluginContext},
+        plugin::{LenzPluginContext, LenzPluginHandle},
     },
 };
 use libloading::Library;
-use std::{collections::HashMap, fmt::Debug, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use super::{
+    commands, dev,
+    lease::{leased, Lease},
+};
+use crate::app::{dynlib_path, load_dynlib_extension, search_esm_files, App, AppState};
+
+// How long deactivation waits for invokes still running plugin code before
+// giving up on unloading its library
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(5);
 
-use crate::app::{load_dynlib_extension, search_esm_files, App};
+const UNLOADING_ERROR: &str =
+    "Invokes are still running, the library stays loaded until they finish";
 
 pub struct Extension {
     path: PathBuf,
+    server_url: String,
     is_builtin: bool,
     plugin_context: LenzPluginContext,
-    plugin_instance: Option<Box<dyn LenzPlugin>>,
+    plugin_instance: Option<LenzPluginHandle>,
     dynlib: Option<Library>,
+    // In dev mode the library is loaded from a copy, removed once unloaded
+    dynlib_copy: Option<PathBuf>,
+    // Why the extension failed to activate, it stays listed without any of
+    // its commands or files
+    error: Option<ExtensionError>,
+    // Deactivated, but invokes kept its library from being unloaded, see
+    // `finish_unload`
+    unloading: bool,
+    lease: Lease,
 }
 
 impl Debug for Extension {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Extension")
             .field("path", &self.path)
+            .field("server_url", &self.server_url)
             .field("is_builtin", &self.is_builtin)
             .field("plugin_context", &self.plugin_context)
             .field(
                 "plugin_instance",
                 if self.plugin_instance.is_some() {
-                    &"Some(Box<dyn LenzPlugin>)"
+                    &"Some(LenzPluginHandle)"
                 } else {
                     &"None"
                 },
             )
             .field("dynlib", &self.dynlib)
+            .field("dynlib_copy", &self.dynlib_copy)
+            .field("error", &self.error)
+            .field("unloading", &self.unloading)
             .finish()
     }
 }
 
 impl Extension {
-    pub fn from_dir(path: &PathBuf) -> Result<Self, ExtensionError> {
+    pub fn from_dir(
+        path: &PathBuf,
+        events: EventBus,
+        server_url: String,
+    ) -> Result<Self, ExtensionError> {
         ExtensionManifest::from_path(path).inspect_err(|e| {
             println!("Failed to load extension at {:?}: {}", path, e);
         }).map(|manifest| {
             let built_in_extensions_dir = lenz_core::config::util::built_in_extensions();
+            let lease = Lease::default();
+
+            // Event listeners of the plugin hold the lease while they run
+            let events = events.with_lease(Arc::downgrade(&lease));
 
             let mut ext = Extension {
-                plugin_context: LenzPluginContext::new(manifest),
+                plugin_context: LenzPluginContext::new(manifest, events),
                 path: path.clone(),
+                server_url,
                 dynlib: None,
+                dynlib_copy: None,
                 is_builtin: path.starts_with(built_in_extensions_dir),
                 plugin_instance: None,
+                error: None,
+                unloading: false,
+                lease,
             };
 
 
             ext.plugin_context
                 .import_map
-                .extend(search_esm_files(&ext.dir().join("esm"), Some(&ext)));
+                .extend(search_esm_files(&ext.dir().join("esm"), Some(&ext), &ext.server_url));
 
-            return ext;
+            ext
         })
     }
 
@@ -69,13 +114,30 @@ impl Extension {
             "is_builtin": self.is_builtin(),
             "esm_url": self.esm_endpoint(),
             "public_url": self.www_endpoint(),
+            "status": if self.unloading {
+                "unloading"
+            } else if self.error.is_some() {
+                "failed"
+            } else {
+                "active"
+            },
+            "error": if self.unloading {
+                Some(UNLOADING_ERROR.to_string())
+            } else {
+                self.error.as_ref().map(|e| e.to_string())
+            },
         })
     }
 
+    pub fn is_unloading(&self) -> bool {
+        self.unloading
+    }
+
     pub fn is_builtin(&self) -> bool {
         self.is_builtin
     }
 
+    #[allow(dead_code)]
     pub fn import_map(&self) -> HashMap<String, String> {
         self.plugin_context.import_map.clone()
     }
@@ -97,7 +159,7 @@ impl Extension {
     }
 
     pub fn base_url(&self) -> String {
-        format!("{BASE_URL}{}", self.endpoint())
+        format!("{}{}", self.server_url, self.endpoint())
     }
 
     pub fn manifest(&self) -> &ExtensionManifest {
@@ -108,6 +170,7 @@ impl Extension {
         &self.path
     }
 
+    #[allow(dead_code)]
     pub fn has_main_script(&self) -> bool {
         !self
             .manifest()
@@ -142,23 +205,83 @@ impl Extension {
         let manifest = self.manifest();
 
         if let Some(lib) = manifest.dynlib.clone() {
-            let (plugin, lib) =
-                load_dynlib_extension(self.dir().join(lib), &mut self.plugin_context)
-                    .expect("Failed to load dynlib");
+            let mut lib_path = dynlib_path(self.dir().join(lib));
+
+            if app.config.dev_mode {
+                match dev::copy_dynlib(&app.config.temp_dir, &id, &lib_path) {
+                    Ok(copy) => {
+                        lib_path = copy.clone();
+                        self.dynlib_copy = Some(copy);
+                    }
+                    Err(e) => {
+                        println!("Failed to activate extension {}: {}", id, e);
+
+                        self.error = Some(e);
+                        extension_host.add(self);
+                        return;
+                    }
+                }
+            }
 
-            self.dynlib = Some(lib);
-            self.plugin_instance = Some(plugin);
+            match load_dynlib_extension(lib_path, &mut self.plugin_context) {
+                Ok((plugin, lib)) => {
+                    self.dynlib = Some(lib);
+                    self.plugin_instance = Some(plugin);
+                }
+                Err(e) => {
+                    println!("Failed to activate extension {}: {}", id, e);
+
+                    if let Some(copy) = self.dynlib_copy.take() {
+                        std::fs::remove_file(copy).ok();
+                    }
+
+                    self.error = Some(e);
+                    extension_host.add(self);
+                    return;
+                }
+            }
         }
 
         static_files.add(&self.endpoint(), self.dir().clone());
 
         import_map.extend(self.plugin_context.import_map.clone());
-        invoke_handlers.extend(self.plugin_context.invoke_handlers.clone());
+        invoke_handlers.extend(
+            self.plugin_context
+                .invoke_handlers
+                .iter()
+                .map(|(command, handler)| {
+                    (command.clone(), leased(handler.clone(), self.lease.clone()))
+                })
+                .collect(),
+        );
+
+        for command in self.plugin_context.invoke_handlers.keys() {
+            let info = self
+                .plugin_context
+                .invoke_handlers_info
+                .get(command)
+                .cloned()
+                .unwrap_or_else(|| InvokeHandlerInfo::new(command).with_extension(&id));
+
+            invoke_handlers.describe(info);
+        }
 
         extension_host.add(self);
     }
 
-    pub async fn deactivate(mut self, app: App) {
+    // Hands a file the user opened the agent with to the plugin, which runs
+    // its code, so it holds the lease like invokes do
+    pub fn open(&self, file: &Path) {
+        let _lease = self.lease.clone();
+
+        for handler in &self.plugin_context.open_handlers {
+            handler(file);
+        }
+    }
+
+    // False when invokes kept running past the timeout. The extension is then
+    // listed as unloading, and only unloaded once they finish.
+    pub async fn deactivate(mut self, app: App) -> bool {
         let id = self.id();
         
         {
@@ -169,6 +292,12 @@ impl Extension {
             }
         }
 
+        for subscription in self.plugin_context.subscriptions.drain(..) {
+            self.plugin_context.events.off(&subscription);
+        }
+
+        self.plugin_context.open_handlers.clear();
+
         {
             let mut import_map = app.import_map.write().await;
 
@@ -182,9 +311,106 @@ impl Extension {
             static_files.remove(&self.endpoint());
         }
 
+        if !self.unload(&id).await {
+            println!(
+                "Extension {} still has invokes running, its library stays loaded until they finish",
+                id
+            );
+
+            let lease = Arc::downgrade(&self.lease);
+            self.unloading = true;
+            app.extension_host.write().await.add(self);
+
+            tokio::spawn(finish_unload(Arc::downgrade(&app), id, lease));
+            return false;
+        }
+
         {
             let mut extension_host = app.extension_host.write().await;
             extension_host.remove(&id);
         }
+
+        true
     }
+
+    // Nothing can reach the plugin anymore, it gets to release its resources
+    // before the library goes away with all of its code. False when invokes
+    // still run its code after `UNLOAD_TIMEOUT`, the library is kept.
+    async fn unload(&mut self, id: &str) -> bool {
+        if let Some(mut plugin) = self.plugin_instance.take() {
+            if !plugin.destroy(&mut self.plugin_context) {
+                println!("Extension {} panicked while being destroyed", id);
+            }
+        }
+
+        if self.dynlib.is_none() {
+            return true;
+        }
+
+        let started = Instant::now();
+
+        while Arc::strong_count(&self.lease) > 1 && started.elapsed() < UNLOAD_TIMEOUT {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        if Arc::strong_count(&self.lease) > 1 {
+            return false;
+        }
+
+        self.release();
+        true
+    }
+
+    fn release(&mut self) {
+        drop(self.dynlib.take());
+
+        if let Some(copy) = self.dynlib_copy.take() {
+            std::fs::remove_file(copy).ok();
+        }
+    }
+}
+
+// Running code from an unloaded library crashes the agent, a library still in
+// use is leaked instead. Only happens when the agent exits mid invoke.
+impl Drop for Extension {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.lease) > 1 {
+            std::mem::forget(self.dynlib.take());
+        }
+    }
+}
+
+// Waits for the invokes that kept an extension from unloading, then unloads
+// it. It stays unloaded, even when still enabled, activating it again is up
+// to the user.
+async fn finish_unload(app: Weak<AppState>, id: String, lease: Weak<()>) {
+    while lease.strong_count() > 1 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let Some(app) = app.upgrade() else {
+        return;
+    };
+
+    let extension = {
+        let mut extension_host = app.extension_host.write().await;
+
+        match extension_host.get(&id) {
+            Some(extension) if Arc::as_ptr(&extension.lease) == lease.as_ptr() => {
+                extension_host.remove(&id)
+            }
+            _ => None,
+        }
+    };
+
+    let Some(mut extension) = extension else {
+        return;
+    };
+
+    extension.release();
+    drop(extension);
+
+    println!("Extension {} unloaded", id);
+
+    commands::announce(&app, &id).await;
 }
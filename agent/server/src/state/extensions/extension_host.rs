@@ -1,16 +1,22 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use lenz_core::{config::ExtensionsConfig, events::EventBus};
 
 use super::Extension;
 
 pub struct ExtensionHost {
     config: Arc<lenz_core::config::AgentConfig>,
+    events: EventBus,
+    settings: ExtensionsConfig,
     pub extensions: HashMap<String, Extension>,
 }
 
 impl ExtensionHost {
-    pub fn new(config: Arc<lenz_core::config::AgentConfig>) -> Self {
+    pub fn new(config: Arc<lenz_core::config::AgentConfig>, events: EventBus) -> Self {
         Self {
+            settings: ExtensionsConfig::load(&config.settings_file),
             config,
+            events,
             extensions: HashMap::new(),
         }
     }
@@ -20,34 +26,61 @@ impl ExtensionHost {
     }
 
     pub fn search_extensions(&self) -> impl Iterator<Item = Extension> {
-        return self
+        let events = self.events.clone();
+        let server_url = self.config.server.base_url();
+
+        self
             .config
             .extensions_search_paths
             .clone()
             .into_iter()
             .filter_map(|path| {
                 if path.is_dir() {
-                    if let Ok(entries) = std::fs::read_dir(&path) {
-                        Some(entries)
-                    } else {
-                        None
-                    }
+                    std::fs::read_dir(&path).ok()
                 } else {
                     None
                 }
             })
             .flatten()
-            .filter_map(|entry| {
+            .filter_map(move |entry| {
                 if let Ok(entry) = entry {
                     if entry.path().is_dir() {
-                        Extension::from_dir(&entry.path()).ok()
+                        Extension::from_dir(&entry.path(), events.clone(), server_url.clone()).ok()
                     } else {
                         None
                     }
                 } else {
                     None
                 }
-            });
+            })
+    }
+
+    // A fresh copy of the extension from disk, not activated yet
+    pub fn find(&self, id: &str) -> Option<Extension> {
+        self.search_extensions().find(|extension| extension.id() == id)
+    }
+
+    pub fn is_enabled(&self, id: &str) -> bool {
+        self.settings.is_enabled(id)
+    }
+
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) -> std::io::Result<()> {
+        let changed = if enabled {
+            self.settings.disabled.remove(id)
+        } else {
+            self.settings.disabled.insert(id.to_string())
+        };
+
+        if changed {
+            self.settings.save(&self.config.settings_file)?;
+        }
+
+        Ok(())
+    }
+
+    // Deactivated, with its library still in use, see `Extension::deactivate`
+    pub fn is_unloading(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|extension| extension.is_unloading())
     }
 
     pub fn has(&self, id: &str) -> bool {
@@ -58,8 +91,14 @@ impl ExtensionHost {
         self.extensions.insert(extension.id(), extension);
     }
 
-    pub fn remove(&mut self, id: &str) {
-        self.extensions.remove(id);
+    pub fn remove(&mut self, id: &str) -> Option<Extension> {
+        self.extensions.remove(id)
+    }
+
+    pub fn open(&self, file: &Path) {
+        for extension in self.extensions.values() {
+            extension.open(file);
+        }
     }
 
     pub async fn get_extensions_json(&self) -> serde_json::Value {
@@ -0,0 +1,108 @@
+use std::{
+    future::Future,
+    io::Read,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use lenz_core::invoke::{InvokeHandler, InvokeResult, InvokeStream};
+
+// Held by everything that may still run code from a plugin library. The
+// library is only unloaded once its extension holds the last one.
+pub type Lease = Arc<()>;
+
+// Keeps the lease until `inner` is dropped, fields drop in declaration order
+struct Leased<T> {
+    inner: T,
+    _lease: Lease,
+}
+
+impl<T: Future + Unpin> Future for Leased<T> {
+    type Output = T::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+impl<T: Stream + Unpin> Stream for Leased<T> {
+    type Item = T::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<T: Read> Read for Leased<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buffer).map_err(detach_error)
+    }
+}
+
+// `Bytes` and `io::Error` made by the plugin drop through its own vtables,
+// they are copied into values owned by the agent
+fn detach_error(e: std::io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind(), e.to_string())
+}
+
+fn detach_stream(stream: InvokeStream, lease: Lease) -> InvokeStream {
+    match stream {
+        InvokeStream::Bytes(inner) => InvokeStream::bytes(
+            Leased {
+                inner,
+                _lease: lease,
+            }
+            .map(|chunk| {
+                chunk
+                    .map(|bytes| Bytes::from(Vec::from(bytes)))
+                    .map_err(detach_error)
+            }),
+        ),
+        InvokeStream::JsonLines(inner) => InvokeStream::json_lines(
+            Leased {
+                inner,
+                _lease: lease,
+            }
+            .map(|line| line.map_err(detach_error)),
+        ),
+        // Also dropped on the thread reading it, once it is done
+        InvokeStream::Reader(inner) => InvokeStream::from_reader(Leased {
+            inner,
+            _lease: lease,
+        }),
+    }
+}
+
+fn detach(result: InvokeResult, lease: Lease) -> InvokeResult {
+    match result {
+        InvokeResult::Binary(bytes) => InvokeResult::Binary(Vec::from(bytes).into()),
+        InvokeResult::Stream(stream) => InvokeResult::Stream(detach_stream(stream, lease)),
+        InvokeResult::Partial(range, stream) => {
+            InvokeResult::Partial(range, detach_stream(stream, lease))
+        }
+        InvokeResult::WithMeta(result, meta) => detach(*result, lease).with_meta(meta),
+        result => result,
+    }
+}
+
+// Wraps a plugin handler so the lease is held while it runs and for as long
+// as the stream it returned is being read
+pub fn leased(handler: Arc<InvokeHandler>, lease: Lease) -> Arc<InvokeHandler> {
+    let handler = Leased {
+        inner: handler,
+        _lease: lease,
+    };
+
+    Arc::new(move |request| {
+        let lease = handler._lease.clone();
+        let future = Leased {
+            inner: (handler.inner)(request),
+            _lease: lease.clone(),
+        };
+
+        Box::pin(async move { detach(future.await, lease) })
+    })
+}
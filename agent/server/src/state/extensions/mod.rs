@@ -1,5 +1,8 @@
+mod commands;
+mod dev;
 mod extension;
 mod extension_host;
+mod lease;
 
 pub use extension::Extension;
 pub use extension_host::ExtensionHost;
@@ -7,14 +10,27 @@ pub use extension_host::ExtensionHost;
 use crate::app::App;
 
 pub async fn init(app: App) {
+    commands::register(&app).await;
+
+    if app.config.dev_mode {
+        dev::remove_stale_copies(&app.config.temp_dir);
+    }
+
     let extension_host = app.extension_host.read().await;
-    let extensions = extension_host.search_extensions();
+    let extensions = extension_host
+        .search_extensions()
+        .filter(|extension| extension_host.is_enabled(&extension.id()))
+        .collect::<Vec<_>>();
 
     drop(extension_host);
 
     for extension in extensions {
         extension.activate(app.clone()).await;
     }
+
+    if app.config.dev_mode {
+        dev::watch(&app);
+    }
 }
 
 pub async fn shutdown(app: App) {
@@ -1,18 +1,107 @@
-use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use bytes::{Bytes, BytesMut};
 use hyper::body::Incoming;
 use lenz_core::invoke::{
-    form::{Form, FormFile, FormValue},
-    InvokeHandler, InvokeRequest, InvokeResult,
+    form::{Form, FormFile, FormValue, SpooledFile},
+    InvokeError, InvokeHandler, InvokeHandlerInfo, InvokeMiddleware, InvokeRequest,
+    InvokeResult, Next,
 };
 
 use futures_util::StreamExt;
-use http::{header::CONTENT_TYPE, Request};
-use http_body_util::BodyStream;
-use mime_guess::mime::APPLICATION_OCTET_STREAM;
-use multer::Multipart;
+use http::{
+    header::{CONTENT_TYPE, RANGE},
+    Request,
+};
+use http_body_util::{BodyExt, BodyStream};
+use mime_guess::{
+    mime::{APPLICATION_JSON, APPLICATION_OCTET_STREAM},
+    Mime,
+};
+use multer::{Field, Multipart};
+use tokio::io::AsyncWriteExt;
+
+// Uploaded parts bigger than this are written to a temporary file instead of memory
+const SPOOL_THRESHOLD: usize = 1024 * 1024;
+
+static SPOOL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+enum FieldData {
+    Memory(Bytes),
+    Spooled(SpooledFile),
+}
+
+fn spool_file_path(spool_dir: &Path) -> std::path::PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    spool_dir.join(format!(
+        "upload-{}-{}-{}",
+        std::process::id(),
+        timestamp,
+        SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+async fn read_field(
+    field: &mut Field<'_>,
+    filename: Option<String>,
+    content_type: Mime,
+    spool_dir: &Path,
+) -> std::io::Result<FieldData> {
+    let mut buffer = BytesMut::new();
+    let mut spool: Option<(tokio::fs::File, SpooledFile)> = None;
+
+    while let Some(chunk) = field.chunk().await.map_err(std::io::Error::other)? {
+        match spool.as_mut() {
+            Some((file, spooled)) => {
+                file.write_all(&chunk).await?;
+                spooled.size += chunk.len() as u64;
+            }
+            None if buffer.len() + chunk.len() > SPOOL_THRESHOLD => {
+                crate::temp::prepare(spool_dir)?;
 
-pub async fn request_to_form(request: Request<Incoming>) -> Option<Form> {
+                let spooled = SpooledFile {
+                    filename: filename.clone(),
+                    content_type: content_type.clone(),
+                    path: spool_file_path(spool_dir),
+                    size: (buffer.len() + chunk.len()) as u64,
+                };
+
+                let mut file =
+                    tokio::fs::File::from_std(crate::temp::create_file(&spooled.path)?);
+                file.write_all(&buffer).await?;
+                file.write_all(&chunk).await?;
+                buffer.clear();
+
+                spool = Some((file, spooled));
+            }
+            None => buffer.extend_from_slice(&chunk),
+        }
+    }
+
+    match spool {
+        Some((mut file, spooled)) => {
+            file.flush().await?;
+            Ok(FieldData::Spooled(spooled))
+        }
+        None => Ok(FieldData::Memory(buffer.freeze())),
+    }
+}
+
+pub async fn request_to_form(request: Request<Incoming>, spool_dir: &Path) -> Option<Form> {
     let (parts, body) = request.into_parts();
 
     let boundary = parts
@@ -28,32 +117,43 @@ pub async fn request_to_form(request: Request<Incoming>) -> Option<Form> {
 
     let mut form = Form::new();
 
-    while let Some(field) = multipart.next_field().await.ok()? {
+    while let Some(mut field) = multipart.next_field().await.ok()? {
         let name = field
             .name()
-            .clone()
             .map(|name| name.to_string())
             .unwrap_or_default();
 
         if let Some(filename) = field.file_name().map(|name| name.to_string()) {
-            
-            if filename == "blob" {
-                form.append(name, FormValue::Bytes(field.bytes().await.unwrap_or_default()));
+            let content_type = field
+                .content_type()
+                .cloned()
+                .unwrap_or(APPLICATION_OCTET_STREAM);
+
+            let filename = if filename == "blob" {
+                None
             } else {
-                let content_type = field
-                    .content_type()
-                    .cloned()
-                    .unwrap_or(APPLICATION_OCTET_STREAM);
-                let data = field.bytes().await.unwrap_or_default();
+                Some(filename)
+            };
 
-                let file = FormFile {
+            let data = match read_field(&mut field, filename.clone(), content_type.clone(), spool_dir).await {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to read form field {:?}: {}", name, e);
+                    return None;
+                }
+            };
+
+            let value = match (data, filename) {
+                (FieldData::Spooled(spooled), _) => FormValue::Spooled(spooled),
+                (FieldData::Memory(data), None) => FormValue::Bytes(data),
+                (FieldData::Memory(data), Some(filename)) => FormValue::File(FormFile {
                     filename,
                     content_type,
                     data,
-                };
+                }),
+            };
 
-                form.append(name, FormValue::File(file));
-            }
+            form.append(name, value);
         } else {
             let text = field.text().await.unwrap_or_default();
             form.append(name, FormValue::Text(text));
@@ -63,17 +163,93 @@ pub async fn request_to_form(request: Request<Incoming>) -> Option<Form> {
     Some(form)
 }
 
+pub fn json_to_form(args: serde_json::Map<String, serde_json::Value>) -> Form {
+    let mut form = Form::new();
+
+    for (key, value) in args {
+        let values = match value {
+            serde_json::Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            match value {
+                serde_json::Value::Null => {}
+                serde_json::Value::String(text) => form.append(key.clone(), FormValue::Text(text)),
+                value => form.append(key.clone(), FormValue::Text(value.to_string())),
+            }
+        }
+    }
+
+    form
+}
+
+#[derive(Clone, Default)]
+pub struct CommandCatalog {
+    commands: Arc<RwLock<HashMap<String, InvokeHandlerInfo>>>,
+}
+
+impl CommandCatalog {
+    pub fn list(&self) -> Vec<InvokeHandlerInfo> {
+        let mut commands = self
+            .commands
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        commands.sort_by(|a, b| a.command.cmp(&b.command));
+        commands
+    }
+
+    pub fn get(&self, command: &str) -> Option<InvokeHandlerInfo> {
+        self.commands.read().unwrap().get(command).cloned()
+    }
+
+    fn register(&self, command: &str) {
+        self.commands
+            .write()
+            .unwrap()
+            .entry(command.to_string())
+            .or_insert_with(|| InvokeHandlerInfo::new(command));
+    }
+
+    fn describe(&self, info: InvokeHandlerInfo) {
+        self.commands
+            .write()
+            .unwrap()
+            .insert(info.command.clone(), info);
+    }
+
+    fn remove(&self, command: &str) {
+        self.commands.write().unwrap().remove(command);
+    }
+}
+
 pub struct InvokeHandlers {
     pub handlers: HashMap<String, Arc<InvokeHandler>>,
+    middlewares: Arc<Vec<Arc<InvokeMiddleware>>>,
+    catalog: CommandCatalog,
 }
 
 impl InvokeHandlers {
     pub fn new() -> Self {
         Self {
             handlers: HashMap::new(),
+            middlewares: Arc::new(Vec::new()),
+            catalog: CommandCatalog::default(),
         }
     }
 
+    pub fn catalog(&self) -> CommandCatalog {
+        self.catalog.clone()
+    }
+
+    pub fn describe(&mut self, info: InvokeHandlerInfo) {
+        self.catalog.describe(info);
+    }
+
     pub fn add<F>(&mut self, command: &str, handler: F)
     where
         F: Fn(InvokeRequest) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
@@ -82,30 +258,152 @@ impl InvokeHandlers {
             + Sync,
     {
         self.handlers.insert(command.to_string(), Arc::new(handler));
+        self.catalog.register(command);
+    }
+
+    // Middlewares run in the order they were added, wrapping every command
+    pub fn wrap<F>(&mut self, middleware: F)
+    where
+        F: Fn(InvokeRequest, Next) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>
+            + 'static
+            + Send
+            + Sync,
+    {
+        Arc::make_mut(&mut self.middlewares).push(Arc::new(middleware));
     }
 
     pub fn remove(&mut self, command: &str) {
         self.handlers.remove(command);
+        self.catalog.remove(command);
     }
 
     pub fn extend(&mut self, handlers: HashMap<String, Arc<InvokeHandler>>) {
+        for command in handlers.keys() {
+            self.catalog.register(command);
+        }
+
         self.handlers.extend(handlers);
     }
 
-    pub async fn invoke(&self, request: InvokeRequest) -> InvokeResult {
-        if let Some(handler) = self.handlers.get(&request.command) {
-            handler(request).await
-        } else {
-            InvokeResult::Error(format!("No handler for command: {}", request.command))
+    // The future doesn't borrow the registry, so the lock on it can be
+    // released before awaiting. Commands like `extensions.enable` change it.
+    pub fn invoke(
+        &self,
+        request: InvokeRequest,
+    ) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>> {
+        let needs_confirmation = self
+            .catalog
+            .get(&request.command)
+            .is_some_and(|info| info.confirm);
+
+        if needs_confirmation {
+            let error = InvokeError::permission_denied(format!(
+                "Command {} needs the user to allow it, see app.confirm",
+                request.command
+            ))
+            .with_details(serde_json::json!({ "command": request.command }));
+
+            return Box::pin(async move { error.into() });
         }
+
+        self.invoke_confirmed(request)
+    }
+
+    // Runs commands described `with_confirmation` too, only once the user
+    // allowed them
+    pub fn invoke_confirmed(
+        &self,
+        request: InvokeRequest,
+    ) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>> {
+        let handler = match self.handlers.get(&request.command) {
+            Some(handler) => handler.clone(),
+            None => Arc::new(command_not_found),
+        };
+
+        Next::new(self.middlewares.clone(), handler).run(request)
     }
 }
 
-pub async fn get_invoke_request(req: Request<Incoming>) -> Option<InvokeRequest> {
+fn command_not_found(
+    request: InvokeRequest,
+) -> Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>> {
+    let error = InvokeError::not_found(format!("No handler for command: {}", request.command));
+    Box::pin(async move { error.into() })
+}
+
+fn is_json_request(request: &Request<Incoming>) -> bool {
+    request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(|ct| ct.parse::<Mime>().ok())
+        .map(|mime| mime.essence_str() == APPLICATION_JSON.essence_str())
+        .unwrap_or(false)
+}
+
+pub async fn json_request_to_form(request: Request<Incoming>) -> Option<Form> {
+    let body = request.into_body().collect().await.ok()?.to_bytes();
+
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Some(Form::new());
+    }
+
+    match serde_json::from_slice(&body) {
+        Ok(serde_json::Value::Object(args)) => Some(json_to_form(args)),
+        Ok(serde_json::Value::Null) => Some(Form::new()),
+        Ok(_) => {
+            eprintln!("Invoke JSON body must be an object");
+            None
+        }
+        Err(e) => {
+            eprintln!("Invalid invoke JSON body: {}", e);
+            None
+        }
+    }
+}
+
+pub async fn get_invoke_request(req: Request<Incoming>, spool_dir: &Path) -> Option<InvokeRequest> {
     let command = req.uri().path().trim_start_matches('/').to_string();
-    let args = request_to_form(req).await?;
 
-    Some(InvokeRequest { command, args })
+    let args = if is_json_request(&req) {
+        json_request_to_form(req).await?
+    } else {
+        request_to_form(req, spool_dir).await?
+    };
+
+    Some(InvokeRequest::new(command, args))
+}
+
+// Arguments from the query string, the `token` aside. A `Range` header, sent
+// by media elements, becomes the `range` argument.
+pub fn query_invoke_request(req: &Request<Incoming>, command: String) -> Option<InvokeRequest> {
+    let decode = |value: &str| {
+        urlencoding::decode(&value.replace('+', " "))
+            .map(|value| value.into_owned())
+            .ok()
+    };
+
+    let mut args = Form::new();
+    let pairs = req.uri().query().unwrap_or_default().split('&');
+
+    for pair in pairs.filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        if key != "ticket" {
+            args.append(decode(key)?, FormValue::Text(decode(value)?));
+        }
+    }
+
+    let range = req
+        .headers()
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok());
+
+    if let Some(range) = range.filter(|_| args.get_entry("range").is_none()) {
+        args.append("range".to_string(), FormValue::Text(range.to_string()));
+    }
+
+    Some(InvokeRequest::new(command, args))
 }
 
 #[macro_export]
@@ -0,0 +1,60 @@
+use std::{future::Future, pin::Pin, time::Instant};
+
+use lenz_core::invoke::{InvokeError, InvokeRequest, InvokeResult, Next};
+
+use super::invoke_handlers::CommandCatalog;
+
+type InvokeFuture = Pin<Box<dyn Future<Output = InvokeResult> + Send + Sync>>;
+
+pub fn logger(request: InvokeRequest, next: Next) -> InvokeFuture {
+    let command = request.command.clone();
+    let started = Instant::now();
+    let result = next.run(request);
+
+    Box::pin(async move {
+        let result = result.await;
+
+        match &result {
+            InvokeResult::Error(error) => eprintln!(
+                "invoke {} failed in {:.2?}: {}",
+                command,
+                started.elapsed(),
+                error
+            ),
+            result => println!(
+                "invoke {} -> {} in {:.2?}",
+                command,
+                result.label(),
+                started.elapsed()
+            ),
+        }
+
+        result
+    })
+}
+
+// Rejects requests missing any argument listed as `required` in the
+// command's args schema, before the handler runs
+pub fn required_args(
+    catalog: CommandCatalog,
+) -> impl Fn(InvokeRequest, Next) -> InvokeFuture + 'static + Send + Sync {
+    move |request, next| {
+        let missing = catalog.get(&request.command).and_then(|info| {
+            info.args?
+                .get("required")?
+                .as_array()?
+                .iter()
+                .filter_map(|name| name.as_str())
+                .find(|name| request.args.get_entry(name).is_none())
+                .map(|name| name.to_string())
+        });
+
+        match missing {
+            Some(name) => {
+                let error = InvokeError::missing_argument(&name);
+                Box::pin(async move { error.into() })
+            }
+            None => next.run(request),
+        }
+    }
+}
@@ -1,3 +1,4 @@
 pub mod extensions;
 pub mod static_assets;
-pub mod invoke_handlers;
\ No newline at end of file
+pub mod invoke_handlers;
+pub mod middlewares;
\ No newline at end of file
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::PathBuf,
+};
 
 pub struct StaticAssets {
     main_dir: PathBuf,
@@ -38,13 +41,14 @@ impl StaticAssets {
     pub fn add(&mut self, prefix: &str, path: PathBuf) {
         let prefix = normalize_prefix(prefix);
 
-        if self.prefixes.contains_key(&prefix) {
-            eprintln!(
+        match self.prefixes.entry(prefix) {
+            Entry::Occupied(entry) => eprintln!(
                 "Cannot add static folder: prefix {:?} already exists",
-                prefix
-            );
-        } else {
-            self.prefixes.insert(prefix, path);
+                entry.key()
+            ),
+            Entry::Vacant(entry) => {
+                entry.insert(path);
+            }
         }
     }
 
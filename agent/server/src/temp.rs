@@ -0,0 +1,123 @@
+use std::{io, path::Path};
+
+// The temporary folder holds uploads and, in dev mode, the libraries the
+// agent loads. Creates it only readable by the current user, and refuses one
+// that another user could have planted files in.
+#[cfg(unix)]
+pub fn prepare(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+
+    let metadata = dir.symlink_metadata()?;
+
+    if !metadata.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+
+    if metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is owned by another user", dir.display()),
+        ));
+    }
+
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn prepare(dir: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+// A new file only the current user can read, never one that already exists
+pub fn create_file(path: &Path) -> io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("lenz-temp-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn mode(path: &Path) -> u32 {
+        path.metadata().unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn creates_private_dirs() {
+        let dir = dir("create");
+
+        prepare(&dir.join("dynlib")).unwrap();
+
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join("dynlib")), 0o700);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn restricts_existing_dirs() {
+        let dir = dir("restrict");
+
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        prepare(&dir).unwrap();
+
+        assert_eq!(mode(&dir), 0o700);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn refuses_symlinks() {
+        let dir = dir("symlink");
+
+        std::fs::create_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("link")).unwrap();
+
+        assert!(prepare(&dir.join("link")).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn creates_files_only_once() {
+        let dir = dir("file");
+        prepare(&dir).unwrap();
+
+        let path = dir.join("upload");
+        create_file(&path).unwrap();
+
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(
+            create_file(&path).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
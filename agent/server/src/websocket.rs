@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures_util::{SinkExt, StreamExt};
+use http::{
+    header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
+    Request,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use lenz_core::{
+    events::DISCONNECTED_EVENT,
+    invoke::{CancellationToken, InvokeError, InvokeRequest, InvokeResult, InvokeStream},
+};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+
+use crate::{
+    app::App,
+    server::{create_response, full, Body, QuitSignal},
+    state::invoke_handlers::json_to_form,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Invoke {
+        id: u64,
+        command: String,
+        #[serde(default)]
+        args: serde_json::Map<String, serde_json::Value>,
+    },
+    Cancel {
+        id: u64,
+    },
+}
+
+// In-flight invokes of a connection, by client supplied id
+type PendingInvokes = Arc<Mutex<HashMap<u64, CancellationToken>>>;
+
+// Batches of frames waiting to be written. Streams wait for room, so a slow
+// client holds back the reads instead of the agent buffering them.
+const OUTGOING_CAPACITY: usize = 16;
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Result {
+        id: u64,
+        result: &'a str,
+        data: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        meta: Option<serde_json::Value>,
+    },
+    Chunk {
+        id: u64,
+        data: serde_json::Value,
+    },
+    End {
+        id: u64,
+        error: Option<String>,
+    },
+    Event {
+        event: &'a str,
+        data: &'a serde_json::Value,
+    },
+}
+
+impl ServerMessage<'_> {
+    fn into_message(self) -> Message {
+        Message::Text(serde_json::to_string(&self).unwrap())
+    }
+}
+
+pub fn is_upgrade_request(req: &Request<Incoming>) -> bool {
+    let header_contains = |name, value: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|header| header.to_str().ok())
+            .map(|header| {
+                header
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(value))
+            })
+            .unwrap_or(false)
+    };
+
+    header_contains(CONNECTION, "upgrade") && header_contains(UPGRADE, "websocket")
+}
+
+pub async fn resolve_websocket(
+    mut req: Request<Incoming>,
+    app: App,
+    quit_signal: QuitSignal,
+) -> Result<http::Response<Body>, Infallible> {
+    let accept_key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => {
+            return Ok(create_response()
+                .status(400)
+                .body(full("Bad Request"))
+                .unwrap())
+        }
+    };
+
+    let upgrade = hyper::upgrade::on(&mut req);
+
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let stream =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None)
+                        .await;
+
+                handle_connection(stream, app, quit_signal).await;
+            }
+            Err(e) => eprintln!("Erro ao estabelecer conexão WebSocket: {}", e),
+        }
+    });
+
+    Ok(create_response()
+        .status(101)
+        .header(CONNECTION, "Upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(full(""))
+        .unwrap())
+}
+
+async fn handle_connection(
+    stream: WebSocketStream<TokioIo<hyper::upgrade::Upgraded>>,
+    app: App,
+    quit_signal: QuitSignal,
+) {
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed) + 1;
+    let (mut sink, mut stream) = stream.split();
+    let mut events = app.events.subscribe();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<Vec<Message>>(OUTGOING_CAPACITY);
+    let pending = PendingInvokes::default();
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Invoke { id, command, args }) => {
+                        let request = InvokeRequest::new(command, json_to_form(args))
+                            .with_connection(connection);
+
+                        pending
+                            .lock()
+                            .unwrap()
+                            .insert(id, request.cancellation.clone());
+
+                        tokio::spawn(invoke(
+                            id,
+                            request,
+                            app.clone(),
+                            quit_signal.clone(),
+                            outgoing_tx.clone(),
+                            pending.clone(),
+                        ));
+                    }
+                    Ok(ClientMessage::Cancel { id }) => {
+                        if let Some(cancellation) = pending.lock().unwrap().remove(&id) {
+                            cancellation.cancel();
+                        }
+                    }
+                    Err(e) => eprintln!("Mensagem WebSocket inválida: {}", e),
+                },
+                Some(Ok(Message::Ping(payload))) => {
+                    if sink.send(Message::Pong(payload)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    eprintln!("Erro na conexão WebSocket: {}", e);
+                    break;
+                }
+            },
+
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let message = ServerMessage::Event {
+                        event: &event.event,
+                        data: &event.data,
+                    };
+
+                    if sink.send(message.into_message()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Cliente WebSocket atrasado, {} eventos descartados", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+
+            Some(messages) = outgoing_rx.recv() => {
+                let mut messages = futures_util::stream::iter(messages.into_iter().map(Ok));
+
+                if sink.send_all(&mut messages).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Nobody is left to receive the results
+    for (_, cancellation) in pending.lock().unwrap().drain() {
+        cancellation.cancel();
+    }
+
+    // Extensions drop what they kept for it, like `fs.watch`
+    app.events.emit(
+        DISCONNECTED_EVENT,
+        serde_json::json!({ "connection": connection }),
+    );
+
+    sink.close().await.ok();
+}
+
+async fn invoke(
+    id: u64,
+    request: InvokeRequest,
+    app: App,
+    quit_signal: QuitSignal,
+    outgoing: mpsc::Sender<Vec<Message>>,
+    pending: PendingInvokes,
+) {
+    let cancellation = request.cancellation.clone();
+    let invoke = app.invoke_handlers.read().await.invoke(request);
+    let (result, meta) = invoke.await.into_parts();
+    let label = result.label().to_string();
+
+    let mut invoke_stream = None;
+
+    let (data, binary) = match result {
+        InvokeResult::Json(json) => (json, None),
+        InvokeResult::Text(text) => (text.into(), None),
+        InvokeResult::Error(error) => (serde_json::to_value(error).unwrap(), None),
+        InvokeResult::Binary(bytes) => (serde_json::Value::Null, Some(bytes)),
+        // There are no headers for the range, clients asked for it anyway
+        InvokeResult::Stream(stream) | InvokeResult::Partial(_, stream) => {
+            let kind = stream.label().into();
+            invoke_stream = Some(stream);
+
+            (kind, None)
+        }
+        InvokeResult::Void => (serde_json::Value::Null, None),
+        InvokeResult::WithMeta(..) => unreachable!("taken apart above"),
+        InvokeResult::Quit => {
+            if let Some(quit_signal) = quit_signal.write().await.take() {
+                quit_signal.send(()).await.ok();
+            }
+
+            (serde_json::Value::Null, None)
+        }
+    };
+
+    let message = ServerMessage::Result {
+        id,
+        result: &label,
+        data,
+        meta,
+    };
+
+    let mut messages = vec![message.into_message()];
+
+    // Binary payloads are sent as a separate frame right after their result header
+    if let Some(bytes) = binary {
+        messages.push(Message::Binary(bytes.to_vec()));
+    }
+
+    outgoing.send(messages).await.ok();
+
+    if let Some(invoke_stream) = invoke_stream {
+        forward_stream(id, invoke_stream, outgoing, &cancellation).await;
+    }
+
+    pending.lock().unwrap().remove(&id);
+}
+
+async fn forward_stream(
+    id: u64,
+    invoke_stream: InvokeStream,
+    outgoing: mpsc::Sender<Vec<Message>>,
+    cancellation: &CancellationToken,
+) {
+    let cancelled = || Some(InvokeError::cancelled().to_string());
+
+    let error = match invoke_stream {
+        InvokeStream::JsonLines(mut stream) => loop {
+            let next = tokio::select! {
+                next = stream.next() => next,
+                _ = cancellation.cancelled() => break cancelled(),
+            };
+
+            match next {
+                Some(Ok(data)) => {
+                    let chunk = ServerMessage::Chunk { id, data };
+
+                    if outgoing.send(vec![chunk.into_message()]).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(e)) => break Some(e.to_string()),
+                None => break None,
+            }
+        },
+        invoke_stream => {
+            let mut stream = invoke_stream.into_bytes();
+
+            loop {
+                let next = tokio::select! {
+                    next = stream.next() => next,
+                    _ = cancellation.cancelled() => break cancelled(),
+                };
+
+                match next {
+                    Some(Ok(bytes)) => {
+                        let chunk = ServerMessage::Chunk {
+                            id,
+                            data: serde_json::Value::Null,
+                        };
+
+                        let messages = vec![chunk.into_message(), Message::Binary(bytes.to_vec())];
+
+                        if outgoing.send(messages).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(e)) => break Some(e.to_string()),
+                    None => break None,
+                }
+            }
+        }
+    };
+
+    outgoing
+        .send(vec![ServerMessage::End { id, error }.into_message()])
+        .await
+        .ok();
+}
@@ -1,5 +1,6 @@
 import { useEffect, useMemo, useState } from "react";
 import { invoke } from "lenz:invoke";
+import { watch } from "lenz:fs";
 
 import { Entry } from "../../types";
 import { FileListView } from "./FileListView";
@@ -19,6 +20,23 @@ export interface FileListProps {
 
 export function FileList(props: FileListProps) {
   const [entries, setEntries] = useState<Entry[]>([]);
+  const [version, setVersion] = useState(0);
+
+  // Atualiza a listagem quando a pasta muda no disco. Pastas fora dos
+  // escopos do fs não podem ser observadas e continuam estáticas.
+  useEffect(() => {
+    if (!props.path) {
+      return;
+    }
+
+    const unwatch = watch(props.path, () => setVersion((v) => v + 1)).catch(
+      () => null
+    );
+
+    return () => {
+      unwatch.then((unwatch) => unwatch?.());
+    };
+  }, [props.path]);
 
   useEffect(() => {
     if (!props.path) {
@@ -34,7 +52,7 @@ export function FileList(props: FileListProps) {
     }).then((entries: Entry[]) => {
       setEntries(entries);
     });
-  }, [props.path, props.filter, props.sortBy]);
+  }, [props.path, props.filter, props.sortBy, version]);
 
   const viewProps = useMemo(() => ({ entries, ...props }), [entries, props]);
 
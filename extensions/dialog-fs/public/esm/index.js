@@ -1,11 +1,32 @@
 import { OpenFileDialog, SaveFileDialog } from "../www/file-dialog.lenz.es.js";
 import { createWindow } from "lenz:ui";
+import { confirmCall } from "lenz:channel";
+
+// Picking a file in the dialog is what grants the fs commands access to it,
+// once the user allows it in the agent's confirmation window. Only the access
+// the caller needs is asked for.
+async function grantAccess(result, access) {
+  const paths = Array.isArray(result) ? result : result ? [result] : [];
+
+  if (!paths.length) {
+    return result;
+  }
+
+  try {
+    const granted = await confirmCall("fs.grant", { paths, access });
+    return granted ? result : undefined;
+  } catch (e) {
+    console.warn("fs.grant", paths, e);
+    return undefined;
+  }
+}
 
 export function openFile({
   title = "Abrir Arquivo",
   width = 640,
   height = 480,
   filters,
+  access = "read",
 }) {
   return new Promise((resolve) => {
     const w = createWindow({
@@ -17,7 +38,7 @@ export function openFile({
           filters,
           onResult(value) {
             w.close();
-            resolve(value);
+            resolve(grantAccess(value, access));
           },
         }),
     });
@@ -40,7 +61,7 @@ export function saveFile({
           onResult(result) {
             console.log("result", result);
             w.close();
-            resolve(result);
+            resolve(grantAccess(result, "write"));
           },
           filters,
         }),
@@ -1,6 +1,6 @@
 use crate::entry::{Entry, EntryType};
 
-pub fn path_is_hidden(path: &std::path::PathBuf) -> bool {
+pub fn path_is_hidden(path: &std::path::Path) -> bool {
     path.file_name().unwrap().to_string_lossy().starts_with('.')
 }
 
@@ -78,7 +78,7 @@ pub fn get_disk_partitions() -> Vec<Entry> {
             let mount_point = parts[5];
             let used = parts[2];
             
-            return Entry {
+            Entry {
                 kind: EntryType::DiskPartition,
                 name: name.to_string(),
                 path: mount_point.to_string(),
@@ -94,7 +94,7 @@ pub fn get_disk_partitions() -> Vec<Entry> {
             if entry.path == "/" {
                 entry.name = "Raíz do sistema".to_string();
             } else if entry.path.starts_with("/media") {
-                entry.name = entry.path.split('/').last().unwrap().to_string();
+                entry.name = entry.path.split('/').next_back().unwrap().to_string();
             }
 
             entry
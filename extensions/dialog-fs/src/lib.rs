@@ -1,14 +1,31 @@
 use lenz_core::{
     define_invoke_handlers,
     extensions::plugin::{LenzPlugin, LenzPluginContext},
-    invoke::{InvokeRequest, InvokeResult},
+    invoke::{InvokeHandlerInfo, InvokeResult},
 };
+use serde_json::json;
+use serde::Deserialize;
 use traverse::{ListAllOptions, Sort};
 
 mod entry;
 mod folders;
 mod traverse;
 
+#[derive(Deserialize)]
+struct ListArgs {
+    dir: String,
+    #[serde(default)]
+    show_hidden: bool,
+    #[serde(default)]
+    query: String,
+    #[serde(default)]
+    filter: Vec<String>,
+    #[serde(default)]
+    sort_by: Vec<Sort>,
+    #[serde(default)]
+    only_folders: bool,
+}
+
 struct FoldersPlugin;
 
 impl LenzPlugin for FoldersPlugin {
@@ -25,39 +42,48 @@ impl LenzPlugin for FoldersPlugin {
 
                     InvokeResult::Json(serde_json::to_value(entries).unwrap())
                 },
-                "folders.list" => |invoke: InvokeRequest| async move {
-                    let dir = if let Some(dir) = invoke.args.get_text("dir") {
-                        dir.to_string()
-                    } else {
-                        return InvokeResult::Error("Missing `dir` parameter".to_string());
-                    };
-
-                    let show_hidden = invoke.args.get_as::<bool>("show_hidden").unwrap_or_default();
-                    let query = invoke.args.get_text("query").unwrap_or_default().to_string();
-                    let filter = invoke.args.get_all_text("filter").unwrap_or_default().into_iter().map(|f| f.to_string()).collect::<Vec<_>>();
-                    let sort_by: Vec<Sort> = invoke.args.get_all_text("sort_by").and_then(|text| text.iter().map(|s| serde_json::from_str::<Sort>(s).ok()).collect()).unwrap_or_default();
-                    let only_folders = invoke.args.get_as::<bool>("only_folders").unwrap_or_default();
-
+                "folders.list" => typed cancellable |args: ListArgs, cancellation| async move {
                     let options = ListAllOptions {
-                        show_hidden,
-                        query,
-                        filter,
-                        sort_by,
-                        only_folders
+                        show_hidden: args.show_hidden,
+                        query: args.query,
+                        filter: args.filter,
+                        sort_by: args.sort_by,
+                        only_folders: args.only_folders,
                     };
 
-                    let entries = traverse::list(dir, options);
-
-                    return InvokeResult::Json(serde_json::to_value(entries).unwrap());
+                    traverse::list(args.dir, options, &cancellation)
+                        .map(|entries| serde_json::to_value(entries).unwrap())
                 }
             )
-        )
+        );
+
+        context.describe(
+            InvokeHandlerInfo::new("folders.locals")
+                .with_description("Lists the user's well-known folders"),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("folders.disks").with_description("Lists the mounted disks"),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("folders.list")
+                .with_description("Lists the entries of a directory")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "dir": { "type": "string" },
+                        "show_hidden": { "type": "boolean" },
+                        "query": { "type": "string" },
+                        "filter": { "type": "array", "items": { "type": "string" } },
+                        "sort_by": { "type": "array" },
+                        "only_folders": { "type": "boolean" }
+                    },
+                    "required": ["dir"]
+                }))
+                .with_result(json!({ "type": "array", "items": { "type": "object" } })),
+        );
     }
 
     fn destroy(&self, _: &mut LenzPluginContext) {}
 }
 
-#[no_mangle]
-pub fn create_plugin(_: &mut LenzPluginContext) -> Box<dyn LenzPlugin> {
-    Box::new(FoldersPlugin)
-}
+lenz_core::declare_plugin!(|| FoldersPlugin);
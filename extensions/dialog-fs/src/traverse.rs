@@ -1,5 +1,6 @@
 use std::{cmp::Ordering, collections::HashMap, path::PathBuf};
 
+use lenz_core::invoke::{CancellationToken, InvokeError};
 use serde::Deserialize;
 use unicode_normalization::UnicodeNormalization;
 
@@ -21,12 +22,16 @@ pub struct ListAllOptions {
     pub filter: Vec<String>,
 }
 
-pub fn list(dir: String, options: ListAllOptions) -> Vec<Entry> {
+pub fn list(
+    dir: String,
+    options: ListAllOptions,
+    cancellation: &CancellationToken,
+) -> Result<Vec<Entry>, InvokeError> {
     let path = PathBuf::from(dir);
 
     if path.is_symlink() {
         let target = path.read_link().unwrap();
-        return list(target.to_string_lossy().to_string(), options);
+        return list(target.to_string_lossy().to_string(), options, cancellation);
     }
 
     let mut comparators: HashMap<&str, SortComparator> = HashMap::new();
@@ -67,6 +72,8 @@ pub fn list(dir: String, options: ListAllOptions) -> Vec<Entry> {
 
     if path.is_dir() {
         for entry in path.read_dir().unwrap() {
+            cancellation.check()?;
+
             let entry = entry.unwrap();
             let path = entry.path();
             let name = path.file_name().unwrap().to_string_lossy().to_string();
@@ -128,5 +135,5 @@ pub fn list(dir: String, options: ListAllOptions) -> Vec<Entry> {
         });
     }
 
-    entries
+    Ok(entries)
 }
@@ -1,8 +1,123 @@
-import { invoke } from 'lenz:invoke';
+import { BASE_URL, invoke, invokeWithMeta } from 'lenz:invoke';
+import { call, confirmCall, on } from 'lenz:channel';
 
-export function readFile(path) {
-  return invoke('fs.readFile', { path })
+function rangeArgs(path, { offset, length } = {}) {
+  const args = { path }
+
+  if (offset !== undefined) {
+    args.offset = offset
+  }
+  if (length !== undefined) {
+    args.length = length
+  }
+
+  return args
+}
+
+export async function readFile(path, options) {
+  return new Response(await readFileStream(path, options)).arrayBuffer()
+}
+// Conteúdo e versão lidos juntos, para salvar depois com `expectedVersion`
+export async function readFileWithVersion(path) {
+  const { result, meta } = await invokeWithMeta('fs.readFile', { path })
+  const data = new Uint8Array(await new Response(result).arrayBuffer())
+
+  return { data, version: meta.version }
+}
+// URL do arquivo para `<img>`, `<video>` e fontes, que leem só os trechos que precisam.
+// Leva um ticket válido só para este arquivo, nunca o token da sessão
+export async function fileUrl(path) {
+  const { url } = await invoke('app.ticket', {
+    command: 'fs.readFile',
+    args: JSON.stringify({ path }),
+  })
+
+  return `${BASE_URL}${url}`
+}
+export function readTextFile(path, { encoding } = {}) {
+  return invoke('fs.readTextFile', encoding ? { path, encoding } : { path })
+}
+export function writeTextFile(path, data, options = {}) {
+  const args = { path, data }
+
+  for (const key of ['encoding', 'bom', 'lineEnding', 'backup', 'expectedVersion']) {
+    if (options[key] !== undefined) {
+      args[key] = options[key]
+    }
+  }
+
+  return invoke('fs.writeTextFile', args)
+}
+export function readFileStream(path, options) {
+  return invoke('fs.readFile', rangeArgs(path, options))
+}
+export function writeFile(path, data, { backup = 'none', expectedVersion } = {}) {
+  const args = { path, data: new Blob([data]), backup }
+
+  if (expectedVersion) {
+    args.expectedVersion = expectedVersion
+  }
+
+  return invoke('fs.writeFile', args)
+}
+export function appendFile(path, data) {
+  return invoke('fs.appendFile', { path, data: new Blob([data]) })
+}
+export function stat(path) {
+  return invoke('fs.stat', { path })
+}
+export function exists(path) {
+  return invoke('fs.exists', { path })
+}
+export function readDir(path) {
+  return invoke('fs.readDir', { path })
+}
+export function mkdir(path, { recursive = false } = {}) {
+  return invoke('fs.mkdir', { path, recursive })
+}
+export function remove(path, { recursive = false } = {}) {
+  return invoke('fs.remove', { path, recursive })
+}
+export function rename(from, to, { overwrite = false } = {}) {
+  return invoke('fs.rename', { from, to, overwrite })
+}
+export function copy(from, to, { overwrite = false } = {}) {
+  return invoke('fs.copy', { from, to, overwrite })
+}
+export function move(from, to, { overwrite = false } = {}) {
+  return invoke('fs.move', { from, to, overwrite })
+}
+// Pelo canal: o agente encerra o watch quando a conexão fecha, como ao
+// recarregar a página
+export async function watch(path, listener, { recursive = false } = {}) {
+  let id
+  const dispose = on('fs.change', (event) => {
+    if (event.id === id) {
+      listener(event.changes)
+    }
+  })
+
+  try {
+    ({ id } = await call('fs.watch', { path, recursive }))
+  } catch (e) {
+    dispose()
+    throw e
+  }
+
+  return () => {
+    dispose()
+    return call('fs.unwatch', { id })
+  }
+}
+
+export function scopes() {
+  return invoke('fs.scopes')
+}
+// Pede ao usuário, numa janela do agente, acesso aos caminhos. Resolve com
+// `undefined` se ele negar
+export function grant(paths, access = 'read') {
+  return confirmCall('fs.grant', { paths: [].concat(paths), access })
+}
+export function revoke(path) {
+  return invoke('fs.revoke', { path })
 }
-export function writeFile(path, data) {
-  return invoke('fs.writeFile', { path, data: new Blob([data]) })
-}
\ No newline at end of file
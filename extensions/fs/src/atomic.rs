@@ -0,0 +1,336 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use lenz_core::invoke::{form::FormValue, InvokeError};
+use sha1::{Digest, Sha1};
+
+const MAX_BACKUPS: usize = 3;
+const BACKUPS_DIR: &str = "backups";
+const BACKUP_PATH_FILE: &str = "path";
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backup {
+    None,
+    // `file.bak`, `file.bak.1`, ... next to the file
+    Bak,
+    // Timestamped copies under the app data folder
+    AppData,
+}
+
+impl Backup {
+    pub fn parse(value: Option<&str>) -> Result<Self, InvokeError> {
+        match value {
+            None | Some("none") => Ok(Backup::None),
+            Some("bak") => Ok(Backup::Bak),
+            Some("appData") => Ok(Backup::AppData),
+            Some(backup) => Err(InvokeError::invalid_argument(format!(
+                "Invalid backup: {}",
+                backup
+            ))),
+        }
+    }
+}
+
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let id = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    // Same folder as the target, so the final rename can't cross file systems
+    path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), id))
+}
+
+fn rotate_bak(path: &Path) -> std::io::Result<()> {
+    let bak = |index: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".bak");
+
+        if index > 0 {
+            name.push(format!(".{}", index));
+        }
+
+        PathBuf::from(name)
+    };
+
+    for index in (1..MAX_BACKUPS).rev() {
+        let from = bak(index - 1);
+
+        if from.exists() {
+            std::fs::rename(from, bak(index))?;
+        }
+    }
+
+    std::fs::copy(path, bak(0)).map(|_| ())
+}
+
+// One folder per file, named after a hash of its full path since any
+// readable escaping of it would either collide or be too long for a name
+fn app_data_key(path: &Path) -> String {
+    Sha1::digest(path.as_os_str().as_encoded_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn backup_to_app_data(path: &Path) -> std::io::Result<()> {
+    let dir = lenz_core::config::util::app_data()
+        .join(BACKUPS_DIR)
+        .join(app_data_key(path));
+
+    std::fs::create_dir_all(&dir)?;
+
+    // Tells which file the folder has backups of
+    std::fs::write(
+        dir.join(BACKUP_PATH_FILE),
+        path.as_os_str().as_encoded_bytes(),
+    )?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    std::fs::copy(path, dir.join(format!("{}.bak", millis)))?;
+
+    let mut backups = std::fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|backup| {
+            backup
+                .extension()
+                .is_some_and(|extension| extension == "bak")
+        })
+        .collect::<Vec<_>>();
+
+    backups.sort();
+
+    for old in backups.iter().rev().skip(MAX_BACKUPS) {
+        std::fs::remove_file(old).ok();
+    }
+
+    Ok(())
+}
+
+fn backup(path: &Path, backup: Backup) -> std::io::Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    match backup {
+        Backup::None => Ok(()),
+        Backup::Bak => rotate_bak(path),
+        Backup::AppData => backup_to_app_data(path),
+    }
+}
+
+fn write_temp(temp: &Path, data: &FormValue) -> std::io::Result<File> {
+    match data {
+        // Large uploads were already written to disk by the agent
+        FormValue::Spooled(file) => {
+            file.persist(temp)?;
+            std::fs::OpenOptions::new().write(true).open(temp)
+        }
+        data => {
+            let mut reader = data.reader()?;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(temp)?;
+
+            std::io::copy(&mut reader, &mut file)?;
+            file.flush()?;
+
+            Ok(file)
+        }
+    }
+}
+
+// Makes the rename itself durable
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Writes to a temp file next to `path` and renames it over the original, so
+// a crash or a full disk never leaves a truncated file behind
+pub fn write(path: &Path, data: &FormValue, backup_mode: Backup) -> std::io::Result<()> {
+    let temp = temp_path(path);
+
+    let result = (|| {
+        let file = write_temp(&temp, data)?;
+
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        file.sync_all()?;
+        drop(file);
+
+        backup(path, backup_mode)?;
+        std::fs::rename(&temp, path)?;
+
+        if let Some(parent) = path.parent() {
+            sync_dir(parent)?;
+        }
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn text(data: &str) -> FormValue {
+        FormValue::Text(data.to_string())
+    }
+
+    fn read(path: impl AsRef<Path>) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    // Everything in the folder, to catch leftover temp files
+    fn entries(dir: &TempDir) -> Vec<String> {
+        let mut entries = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn parses_backup_modes() {
+        assert_eq!(Backup::parse(None).unwrap(), Backup::None);
+        assert_eq!(Backup::parse(Some("none")).unwrap(), Backup::None);
+        assert_eq!(Backup::parse(Some("bak")).unwrap(), Backup::Bak);
+        assert_eq!(Backup::parse(Some("appData")).unwrap(), Backup::AppData);
+        assert!(Backup::parse(Some("always")).is_err());
+    }
+
+    #[test]
+    fn makes_unique_temp_paths_next_to_the_file() {
+        let path = Path::new("/some/dir/file.txt");
+        let (a, b) = (temp_path(path), temp_path(path));
+
+        assert_ne!(a, b);
+        assert_eq!(a.parent(), path.parent());
+        assert!(a
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(".file.txt."));
+    }
+
+    #[test]
+    fn keys_app_data_backups_by_full_path() {
+        let key = app_data_key(Path::new("/a_b/c"));
+
+        assert_eq!(key.len(), 40);
+        assert_eq!(key, app_data_key(Path::new("/a_b/c")));
+        assert_ne!(key, app_data_key(Path::new("/a/b_c")));
+        assert_ne!(key, app_data_key(Path::new("/a/b/c")));
+    }
+
+    #[test]
+    fn writes_new_files() {
+        let dir = TempDir::new();
+        let path = dir.join("new.txt");
+
+        write(&path, &text("hello"), Backup::None).unwrap();
+
+        assert_eq!(read(&path), "hello");
+        assert_eq!(entries(&dir), ["new.txt"]);
+    }
+
+    #[test]
+    fn replaces_existing_files_without_leftovers() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "a much longer old content");
+
+        write(&path, &text("new"), Backup::None).unwrap();
+
+        assert_eq!(read(&path), "new");
+        assert_eq!(entries(&dir), ["file.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_permissions_of_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let path = dir.write("script.sh", "echo old");
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+        write(&path, &text("echo new"), Backup::None).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+
+    #[test]
+    fn rotates_bak_copies() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "v1");
+
+        for version in ["v2", "v3", "v4", "v5"] {
+            write(&path, &text(version), Backup::Bak).unwrap();
+        }
+
+        assert_eq!(read(&path), "v5");
+        assert_eq!(read(dir.join("file.txt.bak")), "v4");
+        assert_eq!(read(dir.join("file.txt.bak.1")), "v3");
+        assert_eq!(read(dir.join("file.txt.bak.2")), "v2");
+        assert_eq!(
+            entries(&dir),
+            [
+                "file.txt",
+                "file.txt.bak",
+                "file.txt.bak.1",
+                "file.txt.bak.2"
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_backups_of_new_files() {
+        let dir = TempDir::new();
+        let path = dir.join("new.txt");
+
+        write(&path, &text("hello"), Backup::Bak).unwrap();
+
+        assert_eq!(entries(&dir), ["new.txt"]);
+    }
+
+    #[test]
+    fn leaves_nothing_behind_when_failing() {
+        let dir = TempDir::new();
+        let path = dir.join("missing/file.txt");
+
+        assert!(write(&path, &text("hello"), Backup::None).is_err());
+        assert!(entries(&dir).is_empty());
+    }
+}
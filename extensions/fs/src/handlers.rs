@@ -1,22 +1,722 @@
-use lenz_core::invoke::{InvokeRequest, InvokeResult};
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-pub async fn read(invoke: InvokeRequest) -> impl Into<InvokeResult> {
-    match invoke.args.get_text("path") {
-        Some(path) => std::fs::read(path).map_err(|e| e.to_string()),
-        None => Err("Missing `path` argument".to_string()),
+use lenz_core::{
+    events::EventBus,
+    invoke::{
+        form::FormValue, ByteRange, CancellationToken, InvokeError, InvokeErrorCode, InvokeRequest,
+        InvokeResult, InvokeStream,
+    },
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    atomic::{self, Backup},
+    metadata::{version, DirEntry, Stat},
+    scope::{Access, Scopes},
+    text::{self, encoding_for_label, LineEnding},
+    watch::Watches,
+};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// Reads in chunks so big files can be abandoned once the request is cancelled
+fn read_all(
+    mut reader: impl Read,
+    path: &Path,
+    cancellation: &CancellationToken,
+) -> Result<Vec<u8>, InvokeError> {
+    let mut data = Vec::new();
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+
+    loop {
+        cancellation.check()?;
+
+        match reader.read(&mut chunk).map_err(io_error(path))? {
+            0 => return Ok(data),
+            read => data.extend_from_slice(&chunk[..read]),
+        }
     }
 }
 
-pub async fn write(invoke: InvokeRequest) -> impl Into<InvokeResult> {
-    match invoke.args.get_text("path") {
-        Some(path) => match invoke.args.get_bytes("data") {
-            Some(data) => {
-                std::fs::create_dir_all(std::path::Path::new(&path).parent().unwrap())
-                    .map_err(|e| e.to_string())?;
-                return std::fs::write(path, data).map_err(|e| e.to_string());
+// The version is taken before reading, a write in the meantime will conflict
+fn read_to_end(
+    path: &Path,
+    cancellation: &CancellationToken,
+) -> Result<(Vec<u8>, String), InvokeError> {
+    let file = File::open(path).map_err(io_error(path))?;
+    let version = version(&file.metadata().map_err(io_error(path))?);
+
+    Ok((read_all(file, path, cancellation)?, version))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadArgs {
+    path: String,
+    offset: Option<u64>,
+    length: Option<u64>,
+    // A `Range` header value, answered as partial content
+    range: Option<String>,
+}
+
+pub async fn read(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<InvokeResult, InvokeError> {
+    let args = invoke.args.deserialize::<ReadArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+
+    let mut file = File::open(&path).map_err(io_error(&path))?;
+    let metadata = file.metadata().map_err(io_error(&path))?;
+
+    // Files like the ones in `/proc` report no size, they are only read whole
+    let range = match (&args.range, args.offset, args.length) {
+        (Some(range), _, _) => Some(ByteRange::parse(range, metadata.len())?),
+        (None, None, None) => None,
+        (None, offset, length) => Some(ByteRange::new(
+            offset.unwrap_or_default(),
+            length,
+            metadata.len(),
+        )?),
+    };
+
+    let limit = match range {
+        Some(range) => {
+            file.seek(SeekFrom::Start(range.start))
+                .map_err(io_error(&path))?;
+            range.len()
+        }
+        None => u64::MAX,
+    };
+
+    let reader = InvokeStream::from_reader(file.take(limit));
+
+    let result = match range {
+        Some(range) if args.range.is_some() => InvokeResult::Partial(range, reader),
+        // Whole files too, the agent reads them as the client takes them
+        _ => reader.into(),
+    };
+
+    // Taken before reading, a write in the meantime will conflict
+    Ok(result.with_meta(json!({ "version": version(&metadata) })))
+}
+
+#[derive(Deserialize)]
+struct ReadTextArgs {
+    path: String,
+    encoding: Option<String>,
+}
+
+// Text along with how it was stored and its version, for editors that save
+// it back later
+pub async fn read_text(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<ReadTextArgs>()?;
+    let encoding = args
+        .encoding
+        .as_deref()
+        .map(encoding_for_label)
+        .transpose()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+
+    let (data, version) = read_to_end(&path, &invoke.cancellation)?;
+
+    let mut text = serde_json::to_value(text::decode(&data, encoding))?;
+    text["version"] = json!(version);
+
+    Ok(text)
+}
+
+// Conflict when the file changed since the client read `expected` version
+fn check_version(path: &Path, expected: Option<&str>) -> Result<(), InvokeError> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+
+    let current = match std::fs::metadata(path) {
+        Ok(metadata) => Some(version(&metadata)),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(io_error(path)(e)),
+    };
+
+    if current.as_deref() == Some(expected) {
+        Ok(())
+    } else {
+        let change = if current.is_some() {
+            "modified"
+        } else {
+            "removed"
+        };
+
+        Err(InvokeError::conflict(format!(
+            "{} was {} by another program",
+            path.display(),
+            change
+        ))
+        .with_details(json!({
+            "path": path,
+            "expectedVersion": expected,
+            "version": current,
+        })))
+    }
+}
+
+// Shared by `write` and `write_text`, which encodes `data` first
+fn save(
+    scopes: &Scopes,
+    invoke: &InvokeRequest,
+    data: &FormValue,
+) -> Result<serde_json::Value, InvokeError> {
+    let path = invoke
+        .args
+        .get_text("path")
+        .ok_or_else(|| InvokeError::missing_argument("path"))?;
+
+    let path = scopes.resolve(path, Access::Write)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    let backup = Backup::parse(invoke.args.get_text("backup"))?;
+
+    check_version(&path, invoke.args.get_text("expectedVersion"))?;
+    atomic::write(&path, data, backup).map_err(io_error(&path))?;
+
+    let metadata = std::fs::metadata(&path).map_err(io_error(&path))?;
+
+    Ok(json!({ "version": version(&metadata) }))
+}
+
+pub async fn write(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let data = invoke
+        .args
+        .get_entry("data")
+        .ok_or_else(|| InvokeError::missing_argument("data"))?;
+
+    save(&scopes, &invoke, data)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WriteTextArgs {
+    data: String,
+    encoding: Option<String>,
+    #[serde(default)]
+    bom: bool,
+    line_ending: Option<LineEnding>,
+}
+
+pub async fn write_text(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<WriteTextArgs>()?;
+
+    let encoding = match &args.encoding {
+        Some(label) => encoding_for_label(label)?,
+        None => encoding_rs::UTF_8,
+    };
+
+    let data = text::encode(
+        &args.data,
+        encoding,
+        args.bom,
+        args.line_ending.unwrap_or(LineEnding::Lf),
+    );
+
+    save(&scopes, &invoke, &FormValue::Bytes(data.into()))
+}
+
+// Same as the `From<std::io::Error>` conversion, plus the path involved
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> InvokeError + '_ {
+    move |e| {
+        let kind = e.kind();
+
+        InvokeError::from(e).with_details(json!({
+            "kind": format!("{:?}", kind),
+            "path": path,
+        }))
+    }
+}
+
+fn already_exists(path: &Path) -> InvokeError {
+    io_error(path)(std::io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    ))
+}
+
+fn ensure_not_root(scopes: &Scopes, path: &Path) -> Result<(), InvokeError> {
+    if scopes.is_root(path) {
+        Err(
+            InvokeError::permission_denied(format!("Can't remove {}", path.display()))
+                .with_details(json!({ "path": path, "access": Access::Write })),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PathArgs {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct RecursiveArgs {
+    path: String,
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+struct TransferArgs {
+    from: String,
+    to: String,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+pub async fn append(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let path = invoke
+        .args
+        .get_text("path")
+        .ok_or_else(|| InvokeError::missing_argument("path"))?;
+    let data = invoke
+        .args
+        .get_entry("data")
+        .ok_or_else(|| InvokeError::missing_argument("data"))?;
+
+    let path = scopes.resolve(path, Access::Write)?;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(io_error(&path))?;
+
+    let mut reader = data.reader()?;
+    std::io::copy(&mut reader, &mut file).map_err(io_error(&path))?;
+
+    Ok(())
+}
+
+pub async fn stat(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<PathArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+    let metadata = std::fs::metadata(&path).map_err(io_error(&path))?;
+
+    Ok(serde_json::to_value(Stat::new(
+        Path::new(&args.path),
+        &metadata,
+    ))?)
+}
+
+pub async fn exists(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<PathArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+
+    Ok(json!(path.try_exists().map_err(io_error(&path))?))
+}
+
+pub async fn read_dir(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<PathArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+    let mut entries = Vec::new();
+
+    for entry in std::fs::read_dir(&path).map_err(io_error(&path))? {
+        invoke.cancellation.check()?;
+
+        let entry = entry.map_err(io_error(&path))?;
+        let entry_path = entry.path();
+
+        // Not following symlinks, their targets may be outside of the scopes
+        let metadata = entry.metadata().map_err(io_error(&entry_path))?;
+
+        entries.push(DirEntry::new(&entry_path, &metadata));
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(serde_json::to_value(entries)?)
+}
+
+pub async fn mkdir(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let args = invoke.args.deserialize::<RecursiveArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Write)?;
+
+    if args.recursive {
+        std::fs::create_dir_all(&path)
+    } else {
+        std::fs::create_dir(&path)
+    }
+    .map_err(io_error(&path))
+}
+
+fn remove_entry(path: &Path, recursive: bool) -> Result<(), InvokeError> {
+    let metadata = std::fs::symlink_metadata(path).map_err(io_error(path))?;
+
+    if !metadata.is_dir() {
+        std::fs::remove_file(path)
+    } else if recursive {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_dir(path)
+    }
+    .map_err(io_error(path))
+}
+
+pub async fn remove(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let args = invoke.args.deserialize::<RecursiveArgs>()?;
+    let path = scopes.resolve_entry(&args.path, Access::Write)?;
+
+    ensure_not_root(&scopes, &path)?;
+    remove_entry(&path, args.recursive)
+}
+
+// Source and destination of rename, move and copy. An existing destination
+// is only replaced with `overwrite`, and only once the new entry is complete,
+// see `place`.
+fn transfer_paths(
+    scopes: &Scopes,
+    args: &TransferArgs,
+    access: Access,
+) -> Result<(PathBuf, PathBuf), InvokeError> {
+    let from = scopes.resolve_entry(&args.from, access)?;
+    let to = scopes.resolve_entry(&args.to, Access::Write)?;
+
+    std::fs::symlink_metadata(&from).map_err(io_error(&from))?;
+
+    if to.starts_with(&from) && from != to {
+        return Err(InvokeError::invalid_argument(format!(
+            "Can't move or copy {} into itself",
+            from.display()
+        ))
+        .with_details(json!({ "argument": "to" })));
+    }
+
+    if std::fs::symlink_metadata(&to).is_ok() && from != to {
+        if !args.overwrite {
+            return Err(already_exists(&to));
+        }
+
+        ensure_not_root(scopes, &to)?;
+    }
+
+    Ok((from, to))
+}
+
+// Puts `staged` at `to`, replacing whatever is there. A file replaces a file
+// in a single rename. Otherwise the old entry is set aside, and only removed
+// once `staged` took its place, or put back when that fails.
+fn place(staged: &Path, to: &Path) -> std::io::Result<()> {
+    let staged_is_dir = std::fs::symlink_metadata(staged)?.is_dir();
+
+    match std::fs::symlink_metadata(to) {
+        Ok(existing) if existing.is_dir() || staged_is_dir => {
+            let aside = atomic::temp_path(to);
+
+            std::fs::rename(to, &aside)?;
+
+            if let Err(e) = std::fs::rename(staged, to) {
+                std::fs::rename(&aside, to).ok();
+                return Err(e);
             }
-            None => Err("Missing `data` argument".to_string()),
-        },
-        None => Err("Missing `path` argument".to_string()),
+
+            if let Err(e) = remove_entry(&aside, true) {
+                eprintln!("Failed to remove {}: {}", aside.display(), e.message);
+            }
+
+            Ok(())
+        }
+        _ => std::fs::rename(staged, to),
+    }
+}
+
+// Copies into a temporary entry next to `to`, so a failed copy never touches
+// an existing destination
+fn copy_into_place(
+    from: &Path,
+    to: &Path,
+    cancellation: &CancellationToken,
+) -> Result<(), InvokeError> {
+    let staged = atomic::temp_path(to);
+
+    let result = copy_entry(from, &staged, cancellation)
+        .and_then(|_| place(&staged, to).map_err(io_error(to)));
+
+    if result.is_err() && std::fs::symlink_metadata(&staged).is_ok() {
+        remove_entry(&staged, true).ok();
+    }
+
+    result
+}
+
+pub async fn rename(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let args = invoke.args.deserialize::<TransferArgs>()?;
+    let (from, to) = transfer_paths(&scopes, &args, Access::Write)?;
+
+    ensure_not_root(&scopes, &from)?;
+
+    if from == to {
+        return Ok(());
+    }
+
+    place(&from, &to).map_err(io_error(&from))
+}
+
+fn copy_entry(from: &Path, to: &Path, cancellation: &CancellationToken) -> Result<(), InvokeError> {
+    cancellation.check()?;
+
+    let metadata = std::fs::symlink_metadata(from).map_err(io_error(from))?;
+
+    if metadata.is_dir() {
+        std::fs::create_dir_all(to).map_err(io_error(to))?;
+
+        for entry in std::fs::read_dir(from).map_err(io_error(from))? {
+            let entry = entry.map_err(io_error(from))?;
+            copy_entry(&entry.path(), &to.join(entry.file_name()), cancellation)?;
+        }
+
+        Ok(())
+    } else if metadata.is_symlink() {
+        // Copied as links, following them could reach outside of the scopes
+        copy_symlink(from, to)
+    } else {
+        std::fs::copy(from, to).map(|_| ()).map_err(io_error(from))
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> Result<(), InvokeError> {
+    let target = std::fs::read_link(from).map_err(io_error(from))?;
+    std::os::unix::fs::symlink(target, to).map_err(io_error(to))
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _: &Path) -> Result<(), InvokeError> {
+    Err(io_error(from)(ErrorKind::Unsupported.into()))
+}
+
+pub async fn copy(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let args = invoke.args.deserialize::<TransferArgs>()?;
+    let (from, to) = transfer_paths(&scopes, &args, Access::Read)?;
+
+    if from == to {
+        return Ok(());
+    }
+
+    copy_into_place(&from, &to, &invoke.cancellation)
+}
+
+pub async fn move_entry(scopes: Arc<Scopes>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let args = invoke.args.deserialize::<TransferArgs>()?;
+    let (from, to) = transfer_paths(&scopes, &args, Access::Write)?;
+
+    ensure_not_root(&scopes, &from)?;
+
+    if from == to {
+        return Ok(());
     }
-}
\ No newline at end of file
+
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    match place(&from, &to) {
+        // Other file systems need a copy, like `mv` does
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy_into_place(&from, &to, &invoke.cancellation)?;
+            remove_entry(&from, true)
+        }
+        result => result.map_err(io_error(&from)),
+    }
+}
+
+#[derive(Deserialize)]
+struct WatchArgs {
+    path: String,
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+struct UnwatchArgs {
+    id: u64,
+}
+
+pub async fn watch(
+    scopes: Arc<Scopes>,
+    watches: Arc<Watches>,
+    events: EventBus,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let connection = channel_connection(&invoke)?;
+    let args = invoke.args.deserialize::<WatchArgs>()?;
+    let path = scopes.resolve(&args.path, Access::Read)?;
+    let id = watches.watch(connection, path.clone(), args.recursive, events)?;
+
+    Ok(json!({ "id": id, "path": path }))
+}
+
+pub async fn unwatch(watches: Arc<Watches>, invoke: InvokeRequest) -> Result<(), InvokeError> {
+    let connection = channel_connection(&invoke)?;
+    let args = invoke.args.deserialize::<UnwatchArgs>()?;
+    watches.unwatch(connection, args.id)
+}
+
+// Changes are only sent over the channel, watches end with its connection
+fn channel_connection(invoke: &InvokeRequest) -> Result<u64, InvokeError> {
+    invoke.connection.ok_or_else(|| {
+        InvokeError::new(
+            InvokeErrorCode::Unsupported,
+            format!("{} only works over the channel", invoke.command),
+        )
+    })
+}
+
+pub async fn scopes(scopes: Arc<Scopes>, _: InvokeRequest) -> InvokeResult {
+    serde_json::to_value(scopes.list())
+        .map_err(InvokeError::from)
+        .into()
+}
+
+#[derive(Deserialize)]
+struct GrantArgs {
+    paths: Vec<String>,
+    access: Option<Access>,
+}
+
+// Described `with_confirmation`, only runs once the user allowed it
+pub async fn grant(
+    scopes: Arc<Scopes>,
+    invoke: InvokeRequest,
+) -> Result<serde_json::Value, InvokeError> {
+    let args = invoke.args.deserialize::<GrantArgs>()?;
+    let access = args.access.unwrap_or(Access::Read);
+
+    let granted = args
+        .paths
+        .iter()
+        .map(|path| scopes.grant(path, access))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(serde_json::to_value(granted)?)
+}
+
+pub async fn revoke(scopes: Arc<Scopes>, invoke: InvokeRequest) -> InvokeResult {
+    match invoke.args.get_text("path") {
+        Some(path) => scopes.revoke(path).into(),
+        None => InvokeError::missing_argument("path").into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lenz_core::invoke::InvokeErrorCode;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn current_version(path: &Path) -> String {
+        version(&std::fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn reads_with_the_current_version() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+
+        let (data, read_version) = read_to_end(&path, &CancellationToken::new()).unwrap();
+
+        assert_eq!(data, b"hello");
+        assert_eq!(read_version, current_version(&path));
+    }
+
+    #[test]
+    fn cancels_reads() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+        let cancellation = CancellationToken::new();
+
+        cancellation.cancel();
+        let error = read_to_end(&path, &cancellation).unwrap_err();
+
+        assert_eq!(error.code, InvokeErrorCode::Cancelled);
+    }
+
+    #[test]
+    fn changes_version_on_writes() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+        let before = current_version(&path);
+
+        atomic::write(&path, &FormValue::Text("hello world".into()), Backup::None).unwrap();
+
+        assert_ne!(current_version(&path), before);
+    }
+
+    #[test]
+    fn accepts_the_current_version() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+
+        assert!(check_version(&path, Some(&current_version(&path))).is_ok());
+    }
+
+    #[test]
+    fn skips_the_check_without_a_version() {
+        let dir = TempDir::new();
+
+        assert!(check_version(&dir.join("missing.txt"), None).is_ok());
+    }
+
+    #[test]
+    fn conflicts_when_modified() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+        let expected = current_version(&path);
+
+        std::fs::write(&path, "changed elsewhere").unwrap();
+        let error = check_version(&path, Some(&expected)).unwrap_err();
+
+        assert_eq!(error.code, InvokeErrorCode::Conflict);
+        assert!(error.message.contains("modified"));
+
+        let details = error.details.unwrap();
+        assert_eq!(details["expectedVersion"], expected);
+        assert_eq!(details["version"], current_version(&path));
+    }
+
+    #[test]
+    fn conflicts_when_removed() {
+        let dir = TempDir::new();
+        let path = dir.write("file.txt", "hello");
+        let expected = current_version(&path);
+
+        std::fs::remove_file(&path).unwrap();
+        let error = check_version(&path, Some(&expected)).unwrap_err();
+
+        assert_eq!(error.code, InvokeErrorCode::Conflict);
+        assert!(error.message.contains("removed"));
+        assert_eq!(error.details.unwrap()["version"], serde_json::Value::Null);
+    }
+}
@@ -1,29 +1,375 @@
+mod atomic;
 mod handlers;
-use std::sync::Arc;
+mod metadata;
+mod scope;
+#[cfg(test)]
+mod testing;
+mod text;
+mod watch;
+
+use std::{future::Future, sync::Arc};
 
 use handlers as fs;
 
 use lenz_core::{
+    events::{EventBus, DISCONNECTED_EVENT},
     extensions::plugin::{LenzPlugin, LenzPluginContext},
-    invoke::{InvokeRequest, InvokeResult},
+    invoke::{InvokeErrorCode, InvokeHandler, InvokeHandlerInfo, InvokeRequest, InvokeResult},
 };
+use scope::Scopes;
+use serde_json::json;
+use watch::Watches;
+
+pub struct FsLenzExtension {
+    scopes: Arc<Scopes>,
+    watches: Arc<Watches>,
+}
+
+// Hands the scopes over to `handler` and tells the UI when it denied access,
+// so it can ask the user for a grant
+fn scoped<F, Fut, R>(scopes: &Arc<Scopes>, events: &EventBus, handler: F) -> Arc<InvokeHandler>
+where
+    F: Fn(Arc<Scopes>, InvokeRequest) -> Fut + 'static + Send + Sync,
+    Fut: Future<Output = R> + 'static + Send + Sync,
+    R: Into<InvokeResult>,
+{
+    let scopes = scopes.clone();
+    let events = events.clone();
+
+    Arc::new(move |invoke_request| {
+        let command = invoke_request.command.clone();
+        let future = handler(scopes.clone(), invoke_request);
+        let events = events.clone();
 
-pub struct FsLenzExtension;
+        Box::pin(async move {
+            let result = future.await.into();
+
+            if let InvokeResult::Error(e) = &result {
+                if e.code == InvokeErrorCode::PermissionDenied {
+                    events.emit(
+                        "fs.accessDenied",
+                        json!({ "command": command, "details": e.details }),
+                    );
+                }
+            }
+
+            result
+        })
+    })
+}
 
 impl LenzPlugin for FsLenzExtension {
     fn activate(&mut self, context: &mut LenzPluginContext) {
-        context
-            .invoke_handlers
-            .extend(lenz_core::define_invoke_handlers! {
-                "fs.readFile" => fs::read,
-                "fs.writeFile" => fs::write
-            })
+        // Files handed over by a second instance
+        let opened = self.scopes.clone();
+        context.on_open(move |file| {
+            if let Err(e) = opened.open(file) {
+                eprintln!(
+                    "Failed to open fs scope for {}: {}",
+                    file.display(),
+                    e.message
+                );
+            }
+        });
+
+        let watches = self.watches.clone();
+        context.on(DISCONNECTED_EVENT, move |data: serde_json::Value| {
+            if let Some(connection) = data["connection"].as_u64() {
+                watches.disconnect(connection);
+            }
+        });
+
+        let scopes = &self.scopes;
+        let events = &context.events;
+
+        let watch = {
+            let watches = self.watches.clone();
+            let events = events.clone();
+            move |scopes, invoke| fs::watch(scopes, watches.clone(), events.clone(), invoke)
+        };
+        let unwatch = {
+            let watches = self.watches.clone();
+            move |_, invoke| fs::unwatch(watches.clone(), invoke)
+        };
+
+        let handlers = [
+            ("fs.readFile", scoped(scopes, events, fs::read)),
+            ("fs.writeFile", scoped(scopes, events, fs::write)),
+            ("fs.readTextFile", scoped(scopes, events, fs::read_text)),
+            ("fs.writeTextFile", scoped(scopes, events, fs::write_text)),
+            ("fs.appendFile", scoped(scopes, events, fs::append)),
+            ("fs.stat", scoped(scopes, events, fs::stat)),
+            ("fs.exists", scoped(scopes, events, fs::exists)),
+            ("fs.readDir", scoped(scopes, events, fs::read_dir)),
+            ("fs.mkdir", scoped(scopes, events, fs::mkdir)),
+            ("fs.remove", scoped(scopes, events, fs::remove)),
+            ("fs.rename", scoped(scopes, events, fs::rename)),
+            ("fs.copy", scoped(scopes, events, fs::copy)),
+            ("fs.move", scoped(scopes, events, fs::move_entry)),
+            ("fs.watch", scoped(scopes, events, watch)),
+            ("fs.unwatch", scoped(scopes, events, unwatch)),
+            ("fs.scopes", scoped(scopes, events, fs::scopes)),
+            ("fs.grant", scoped(scopes, events, fs::grant)),
+            ("fs.revoke", scoped(scopes, events, fs::revoke)),
+        ];
+
+        context.invoke_handlers.extend(
+            handlers
+                .into_iter()
+                .map(|(command, handler)| (command.to_string(), handler)),
+        );
+
+        let path_args = json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" }
+            },
+            "required": ["path"]
+        });
+        let recursive_args = json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "recursive": { "type": "boolean" }
+            },
+            "required": ["path"]
+        });
+        let transfer_args = json!({
+            "type": "object",
+            "properties": {
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "overwrite": { "type": "boolean" }
+            },
+            "required": ["from", "to"]
+        });
+
+        context.describe(
+            InvokeHandlerInfo::new("fs.readFile")
+                .with_description(
+                    "Streams the contents of a file, or `length` bytes from `offset`. \
+                     `range` takes an HTTP Range header and answers with partial content. \
+                     The version of the file is in the `version` field of the meta",
+                )
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "offset": { "type": "integer", "minimum": 0 },
+                        "length": { "type": "integer", "minimum": 0 },
+                        "range": { "type": "string" }
+                    },
+                    "required": ["path"]
+                }))
+                .with_readonly(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.writeFile")
+                .with_description("Writes data to a file atomically, creating parent directories")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "data": {},
+                        "backup": { "enum": ["none", "bak", "appData"] },
+                        "expectedVersion": { "type": "string" }
+                    },
+                    "required": ["path", "data"]
+                }))
+                .with_result(json!({
+                    "type": "object",
+                    "properties": {
+                        "version": { "type": "string" }
+                    }
+                })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.readTextFile")
+                .with_description("Reads a text file, detecting its encoding and line endings")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "encoding": { "type": "string" }
+                    },
+                    "required": ["path"]
+                }))
+                .with_result(json!({
+                    "type": "object",
+                    "properties": {
+                        "data": { "type": "string" },
+                        "encoding": { "type": "string" },
+                        "bom": { "type": "boolean" },
+                        "lineEnding": { "enum": ["lf", "crlf", "cr"] },
+                        "lossy": { "type": "boolean" },
+                        "version": { "type": "string" }
+                    }
+                }))
+                .with_readonly(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.writeTextFile")
+                .with_description("Writes text with the given encoding and line endings")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "data": { "type": "string" },
+                        "encoding": { "type": "string" },
+                        "bom": { "type": "boolean" },
+                        "lineEnding": { "enum": ["lf", "crlf", "cr"] },
+                        "backup": { "enum": ["none", "bak", "appData"] },
+                        "expectedVersion": { "type": "string" }
+                    },
+                    "required": ["path", "data"]
+                }))
+                .with_result(json!({
+                    "type": "object",
+                    "properties": {
+                        "version": { "type": "string" }
+                    }
+                })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.appendFile")
+                .with_description("Appends data to a file, creating it if needed")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "data": {}
+                    },
+                    "required": ["path", "data"]
+                })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.stat")
+                .with_description("Returns the kind, size and timestamps of a file or folder")
+                .with_args(path_args.clone())
+                .with_result(json!({
+                    "type": "object",
+                    "properties": {
+                        "kind": { "enum": ["file", "directory", "symlink", "other"] },
+                        "size": { "type": "integer" },
+                        "readonly": { "type": "boolean" },
+                        "isSymlink": { "type": "boolean" },
+                        "modifiedAt": { "type": ["integer", "null"] },
+                        "accessedAt": { "type": ["integer", "null"] },
+                        "createdAt": { "type": ["integer", "null"] }
+                    }
+                }))
+                .with_readonly(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.exists")
+                .with_description("Checks whether a file or folder exists")
+                .with_args(path_args.clone())
+                .with_result(json!({ "type": "boolean" }))
+                .with_readonly(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.readDir")
+                .with_description("Lists the entries of a folder, sorted by name")
+                .with_args(path_args)
+                .with_result(json!({ "type": "array" }))
+                .with_readonly(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.mkdir")
+                .with_description("Creates a folder")
+                .with_args(recursive_args.clone()),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.remove")
+                .with_description(
+                    "Removes a file or folder, folders must be empty unless recursive",
+                )
+                .with_args(recursive_args),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.rename")
+                .with_description("Renames a file or folder")
+                .with_args(transfer_args.clone()),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.copy")
+                .with_description("Copies a file or folder with its contents")
+                .with_args(transfer_args.clone()),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.move")
+                .with_description("Moves a file or folder, across file systems if needed")
+                .with_args(transfer_args),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.watch")
+                .with_description(
+                    "Watches a file or folder while its channel connection lasts, reporting changes through `fs.change` events",
+                )
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "recursive": { "type": "boolean" }
+                    },
+                    "required": ["path"]
+                }))
+                .with_result(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "path": { "type": "string" }
+                    }
+                })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.unwatch")
+                .with_description("Stops a watch started by `fs.watch`")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" }
+                    },
+                    "required": ["id"]
+                })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.scopes")
+                .with_description("Lists the folders the fs commands have access to")
+                .with_result(json!({ "type": "array" })),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.grant")
+                .with_description("Grants the fs commands access to folders or files")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "paths": { "type": "array", "items": { "type": "string" } },
+                        "access": { "enum": ["read", "write"] }
+                    },
+                    "required": ["paths"]
+                }))
+                .with_confirmation(),
+        );
+        context.describe(
+            InvokeHandlerInfo::new("fs.revoke")
+                .with_description("Revokes a previous grant")
+                .with_args(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" }
+                    },
+                    "required": ["path"]
+                })),
+        );
     }
 
-    fn destroy(&self, _: &mut LenzPluginContext) {}
+    fn destroy(&self, _: &mut LenzPluginContext) {
+        self.watches.clear();
+    }
 }
 
-#[no_mangle]
-pub fn create_plugin(_: &mut LenzPluginContext) -> Box<dyn LenzPlugin> {
-    Box::new(FsLenzExtension)
-}
+lenz_core::declare_plugin!(|| FsLenzExtension {
+    scopes: Arc::new(Scopes::load()),
+    watches: Arc::default(),
+});
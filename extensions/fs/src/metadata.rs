@@ -0,0 +1,104 @@
+use std::{
+    fs::Metadata,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+// Milliseconds since the epoch, like `Date.now()`
+fn millis(time: std::io::Result<SystemTime>) -> Option<u64> {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as u64)
+}
+
+// Changes whenever the file is modified, used to detect saves that would
+// overwrite changes made by other programs
+pub fn version(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    format!("{:x}-{:x}", modified, metadata.len())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stat {
+    pub kind: FileKind,
+    pub size: u64,
+    pub readonly: bool,
+    pub is_symlink: bool,
+    pub modified_at: Option<u64>,
+    pub accessed_at: Option<u64>,
+    pub created_at: Option<u64>,
+    pub version: String,
+}
+
+impl Stat {
+    // `metadata` follows symlinks, `is_symlink` tells whether `path` is one
+    pub fn new(path: &Path, metadata: &Metadata) -> Self {
+        let kind = if metadata.is_dir() {
+            FileKind::Directory
+        } else if metadata.is_file() {
+            FileKind::File
+        } else if metadata.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        };
+
+        Self {
+            kind,
+            size: metadata.len(),
+            readonly: metadata.permissions().readonly(),
+            is_symlink: path.is_symlink(),
+            modified_at: millis(metadata.modified()),
+            accessed_at: millis(metadata.accessed()),
+            created_at: millis(metadata.created()),
+            version: version(metadata),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirEntry {
+    pub name: String,
+    pub path: String,
+    pub kind: FileKind,
+    pub size: u64,
+    pub is_symlink: bool,
+    pub modified_at: Option<u64>,
+}
+
+impl DirEntry {
+    pub fn new(path: &Path, metadata: &Metadata) -> Self {
+        let stat = Stat::new(path, metadata);
+
+        Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path: path.to_string_lossy().to_string(),
+            kind: stat.kind,
+            size: stat.size,
+            is_symlink: stat.is_symlink,
+            modified_at: stat.modified_at,
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use lenz_core::invoke::InvokeError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const GRANTS_FILE: &str = "fs-grants.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScopeKind {
+    AppData,
+    Project,
+    Granted,
+    // Files opened from the command line, only for the current session
+    Opened,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scope {
+    pub path: PathBuf,
+    pub access: Access,
+    pub kind: ScopeKind,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Grant {
+    path: PathBuf,
+    access: Access,
+}
+
+// Canonicalizes paths that may not exist yet by resolving the deepest
+// existing ancestor. Missing components can't be `..` or dangling symlinks,
+// so the result can't point outside of the resolved ancestor.
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(canonical, |path, name| path.join(name)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if existing.symlink_metadata().is_ok() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!("{} is a dangling symlink", existing.display()),
+                    ));
+                }
+
+                match (existing.parent(), existing.file_name()) {
+                    (Some(parent), Some(name)) => {
+                        missing.push(name.to_owned());
+                        existing = parent;
+                    }
+                    _ => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub struct Scopes {
+    scopes: RwLock<Vec<Scope>>,
+    grants_file: PathBuf,
+}
+
+impl Scopes {
+    pub fn load() -> Self {
+        let app_data = lenz_core::config::util::app_data();
+        let grants_file = app_data.join(GRANTS_FILE);
+
+        let mut scopes = Vec::new();
+
+        let mut add = |path: &Path, access, kind| match canonicalize(path) {
+            Ok(path) => scopes.push(Scope { path, access, kind }),
+            Err(e) => eprintln!("Ignoring fs scope {}: {}", path.display(), e),
+        };
+
+        add(&app_data, Access::Write, ScopeKind::AppData);
+
+        let project = lenz_core::config::util::cli_arg("project")
+            .or_else(|| std::env::var("LENZ_PROJECT_ROOT").ok());
+
+        if let Some(project) = project {
+            add(Path::new(&project), Access::Write, ScopeKind::Project);
+        }
+
+        let grants = std::fs::read(&grants_file)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Vec<Grant>>(&data).ok())
+            .unwrap_or_default();
+
+        for grant in grants {
+            add(&grant.path, grant.access, ScopeKind::Granted);
+        }
+
+        for file in lenz_core::config::util::cli_positional_args() {
+            if let Ok(file) = std::path::absolute(&file) {
+                add(&file, Access::Write, ScopeKind::Opened);
+            }
+        }
+
+        Self {
+            scopes: RwLock::new(scopes),
+            grants_file,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Scope> {
+        self.scopes.read().unwrap().clone()
+    }
+
+    fn save_grants(&self, scopes: &[Scope]) -> std::io::Result<()> {
+        let grants = scopes
+            .iter()
+            .filter(|scope| scope.kind == ScopeKind::Granted)
+            .map(|scope| Grant {
+                path: scope.path.clone(),
+                access: scope.access,
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(parent) = self.grants_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(&self.grants_file, serde_json::to_vec_pretty(&grants)?)
+    }
+
+    pub fn grant(&self, path: &str, access: Access) -> Result<Scope, InvokeError> {
+        let path = canonicalize(&absolute(path)?)?;
+        let mut scopes = self.scopes.write().unwrap();
+
+        scopes.retain(|scope| !(scope.kind == ScopeKind::Granted && scope.path == path));
+
+        let scope = Scope {
+            path,
+            access,
+            kind: ScopeKind::Granted,
+        };
+
+        scopes.push(scope.clone());
+        self.save_grants(&scopes)?;
+
+        Ok(scope)
+    }
+
+    // Only gives access to the file itself, never to a folder
+    pub fn open(&self, path: &Path) -> Result<(), InvokeError> {
+        let path = path.canonicalize()?;
+
+        if !path.is_file() {
+            return Err(
+                InvokeError::invalid_argument(format!("Not a file: {}", path.display()))
+                    .with_details(json!({ "argument": "path" })),
+            );
+        }
+
+        let mut scopes = self.scopes.write().unwrap();
+
+        if !scopes.iter().any(|scope| scope.path == path) {
+            scopes.push(Scope {
+                path,
+                access: Access::Write,
+                kind: ScopeKind::Opened,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn revoke(&self, path: &str) -> Result<(), InvokeError> {
+        let path = canonicalize(&absolute(path)?)?;
+        let mut scopes = self.scopes.write().unwrap();
+
+        scopes.retain(|scope| !(scope.kind == ScopeKind::Granted && scope.path == path));
+        self.save_grants(&scopes)?;
+
+        Ok(())
+    }
+
+    // Canonical form of `path`, as long as some scope gives `access` to it
+    pub fn resolve(&self, path: &str, access: Access) -> Result<PathBuf, InvokeError> {
+        let canonical = canonicalize(&absolute(path)?)?;
+        self.check(path, canonical, access)
+    }
+
+    // Like `resolve`, but doesn't follow `path` itself when it's a symlink,
+    // for commands acting on the entry (remove, rename) and not its target
+    pub fn resolve_entry(&self, path: &str, access: Access) -> Result<PathBuf, InvokeError> {
+        let absolute = absolute(path)?;
+
+        match (absolute.parent(), absolute.file_name()) {
+            (Some(parent), Some(name)) => {
+                let canonical = canonicalize(parent)?.join(name);
+                self.check(path, canonical, access)
+            }
+            _ => self.resolve(path, access),
+        }
+    }
+
+    // Scope folders themselves can't be removed or moved away
+    pub fn is_root(&self, path: &Path) -> bool {
+        self.scopes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|scope| scope.path == path && path.is_dir())
+    }
+
+    fn check(
+        &self,
+        path: &str,
+        canonical: PathBuf,
+        access: Access,
+    ) -> Result<PathBuf, InvokeError> {
+        // Opened files don't give access to anything below them
+        let allowed = self.scopes.read().unwrap().iter().any(|scope| {
+            scope.access >= access
+                && match scope.kind {
+                    ScopeKind::Opened => canonical == scope.path,
+                    _ => canonical.starts_with(&scope.path),
+                }
+        });
+
+        if allowed {
+            Ok(canonical)
+        } else {
+            Err(
+                InvokeError::permission_denied(format!("Access denied to {}", path))
+                    .with_details(json!({ "path": path, "access": access })),
+            )
+        }
+    }
+}
+
+fn absolute(path: &str) -> Result<PathBuf, InvokeError> {
+    let path = PathBuf::from(path);
+
+    if path.is_absolute() {
+        Ok(path)
+    } else {
+        Err(
+            InvokeError::invalid_argument(format!("Path must be absolute: {}", path.display()))
+                .with_details(json!({ "argument": "path" })),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lenz_core::invoke::InvokeErrorCode;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    // `project/` is writable, `docs/` only readable and `outside/` not
+    // reachable at all
+    fn scopes(dir: &TempDir) -> Scopes {
+        dir.write("project/src/main.rs", "");
+        dir.write("docs/readme.md", "");
+        dir.write("outside/secret.txt", "");
+
+        Scopes {
+            scopes: RwLock::new(vec![
+                Scope {
+                    path: dir.join("project"),
+                    access: Access::Write,
+                    kind: ScopeKind::Project,
+                },
+                Scope {
+                    path: dir.join("docs"),
+                    access: Access::Read,
+                    kind: ScopeKind::Granted,
+                },
+            ]),
+            grants_file: dir.join("grants.json"),
+        }
+    }
+
+    fn path(dir: &TempDir, path: &str) -> String {
+        dir.join(path).to_string_lossy().into_owned()
+    }
+
+    fn code(result: Result<PathBuf, InvokeError>) -> InvokeErrorCode {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn resolves_paths_inside_scopes() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            scopes
+                .resolve(&path(&dir, "project/src/main.rs"), Access::Write)
+                .unwrap(),
+            dir.join("project/src/main.rs")
+        );
+        assert_eq!(
+            scopes
+                .resolve(&path(&dir, "docs/readme.md"), Access::Read)
+                .unwrap(),
+            dir.join("docs/readme.md")
+        );
+    }
+
+    #[test]
+    fn denies_paths_outside_of_scopes() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "outside/secret.txt"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+        // A sibling sharing the scope's name as a prefix
+        dir.write("project-old/file", "");
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project-old/file"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn denies_writes_to_read_scopes() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "docs/readme.md"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn resolves_parent_components_before_checking() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            scopes
+                .resolve(&path(&dir, "project/src/../src/main.rs"), Access::Write)
+                .unwrap(),
+            dir.join("project/src/main.rs")
+        );
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project/../outside/secret.txt"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(scopes.resolve(
+                &path(&dir, "project/src/../../outside/new.txt"),
+                Access::Write
+            )),
+            InvokeErrorCode::PermissionDenied
+        );
+        // Can't be resolved without the missing folder, never allowed
+        assert!(scopes
+            .resolve(
+                &path(&dir, "project/missing/../../outside/new.txt"),
+                Access::Write
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn resolves_paths_that_do_not_exist_yet() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            scopes
+                .resolve(&path(&dir, "project/new/dir/file.txt"), Access::Write)
+                .unwrap(),
+            dir.join("project/new/dir/file.txt")
+        );
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "outside/new/file.txt"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn rejects_relative_paths() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert_eq!(
+            code(scopes.resolve("project/src/main.rs", Access::Read)),
+            InvokeErrorCode::InvalidArgument
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_before_checking() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("project/link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("project/src"), dir.join("docs/src")).unwrap();
+
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project/link/secret.txt"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project/link/new.txt"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+        // Pointing into a writable scope from a read only one
+        assert_eq!(
+            scopes
+                .resolve(&path(&dir, "docs/src/main.rs"), Access::Write)
+                .unwrap(),
+            dir.join("project/src/main.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_symlink_entries_without_following_them() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("project/link")).unwrap();
+
+        assert_eq!(
+            scopes
+                .resolve_entry(&path(&dir, "project/link"), Access::Write)
+                .unwrap(),
+            dir.join("project/link")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_dangling_symlinks() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        std::os::unix::fs::symlink(dir.join("outside/missing"), dir.join("project/dangling"))
+            .unwrap();
+
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project/dangling"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "project/dangling/file.txt"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn grants_and_revokes_access() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+        let outside = path(&dir, "outside");
+
+        scopes.grant(&outside, Access::Read).unwrap();
+
+        assert!(scopes
+            .resolve(&path(&dir, "outside/secret.txt"), Access::Read)
+            .is_ok());
+        assert!(std::fs::read_to_string(dir.join("grants.json"))
+            .unwrap()
+            .contains("outside"));
+
+        scopes.revoke(&outside).unwrap();
+
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "outside/secret.txt"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn opens_only_existing_files() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert!(scopes.open(&dir.join("outside")).is_err());
+        assert!(scopes.open(&dir.join("outside/missing.txt")).is_err());
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "outside/secret.txt"), Access::Read)),
+            InvokeErrorCode::PermissionDenied
+        );
+
+        scopes.open(&dir.join("outside/secret.txt")).unwrap();
+
+        assert!(scopes
+            .resolve(&path(&dir, "outside/secret.txt"), Access::Write)
+            .is_ok());
+        assert!(scopes
+            .resolve(&path(&dir, "outside/secret.txt/other"), Access::Write)
+            .is_err());
+        assert_eq!(
+            code(scopes.resolve(&path(&dir, "outside/other.txt"), Access::Write)),
+            InvokeErrorCode::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn knows_scope_roots() {
+        let dir = TempDir::new();
+        let scopes = scopes(&dir);
+
+        assert!(scopes.is_root(&dir.join("project")));
+        assert!(!scopes.is_root(&dir.join("project/src")));
+    }
+}
@@ -0,0 +1,328 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use lenz_core::invoke::InvokeError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// How far into the file to look for `<meta charset>`, same as browsers
+const META_SNIFF_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    // The most common line ending of `text`, LF when there are no lines
+    fn detect(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+
+        for (index, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' if index > 0 && bytes[index - 1] == b'\r' => crlf += 1,
+                b'\n' => lf += 1,
+                b'\r' if bytes.get(index + 1) != Some(&b'\n') => cr += 1,
+                _ => {}
+            }
+        }
+
+        if crlf > lf && crlf >= cr {
+            LineEnding::Crlf
+        } else if cr > lf && cr > crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding, InvokeError> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| {
+        InvokeError::invalid_argument(format!("Unknown encoding: {}", label))
+            .with_details(json!({ "argument": "encoding" }))
+    })
+}
+
+// Charset declared by `<meta charset>` or `<meta http-equiv content>`
+fn meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(META_SNIFF_LEN)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    head.match_indices("charset=").find_map(|(index, pattern)| {
+        let label = head[index + pattern.len()..]
+            .trim_start_matches(['"', '\'', ' '])
+            .split(['"', '\'', ' ', ';', '>', '/'])
+            .next()?;
+
+        // HTML treats these as UTF-8 when declared in the page itself
+        Encoding::for_label(label.as_bytes()).map(|encoding| match encoding {
+            encoding if encoding == UTF_16LE || encoding == UTF_16BE => UTF_8,
+            encoding if encoding.name() == "x-user-defined" => WINDOWS_1252,
+            encoding => encoding,
+        })
+    })
+}
+
+fn guess(bytes: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Decoded {
+    // Always with LF line endings, `line_ending` is what most lines of the
+    // file use
+    pub data: String,
+    pub encoding: &'static str,
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    // Some bytes weren't valid in the encoding and were replaced
+    pub lossy: bool,
+}
+
+// Decodes with `encoding` when given, otherwise from the BOM, a charset
+// `<meta>` or the content itself, in that order
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> Decoded {
+    let bom = Encoding::for_bom(bytes);
+
+    let (encoding, bom_len) = match (encoding, bom) {
+        (Some(encoding), Some((bom_encoding, len))) if encoding == bom_encoding => (encoding, len),
+        (Some(encoding), _) => (encoding, 0),
+        (None, Some((encoding, len))) => (encoding, len),
+        (None, None) => (meta_charset(bytes).unwrap_or_else(|| guess(bytes)), 0),
+    };
+
+    let (text, lossy) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+    let line_ending = LineEnding::detect(&text);
+
+    // Stray line breaks of other styles too, so saving writes the same
+    // ending on every line instead of only on the ones that were edited
+    let data = if text.contains('\r') {
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        text.into_owned()
+    };
+
+    Decoded {
+        data,
+        encoding: encoding.name(),
+        bom: bom_len > 0,
+        line_ending,
+        lossy,
+    }
+}
+
+fn utf16(text: &str, to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+    text.encode_utf16().flat_map(to_bytes).collect()
+}
+
+// The reverse of `decode`. Characters the encoding can't represent become
+// HTML numeric character references.
+pub fn encode(
+    text: &str,
+    encoding: &'static Encoding,
+    bom: bool,
+    line_ending: LineEnding,
+) -> Vec<u8> {
+    let text = match line_ending {
+        LineEnding::Lf => text.replace("\r\n", "\n"),
+        line_ending => text
+            .replace("\r\n", "\n")
+            .replace('\n', line_ending.as_str()),
+    };
+
+    let bom: &[u8] = match (bom, encoding) {
+        (true, encoding) if encoding == UTF_8 => b"\xEF\xBB\xBF",
+        (true, encoding) if encoding == UTF_16LE => b"\xFF\xFE",
+        (true, encoding) if encoding == UTF_16BE => b"\xFE\xFF",
+        _ => b"",
+    };
+
+    // encoding_rs only decodes UTF-16, encoding to it is left to us
+    let data = if encoding == UTF_16LE {
+        utf16(&text, u16::to_le_bytes)
+    } else if encoding == UTF_16BE {
+        utf16(&text, u16::to_be_bytes)
+    } else {
+        encoding.encode(&text).0.into_owned()
+    };
+
+    [bom, &data].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{ISO_8859_2, SHIFT_JIS};
+
+    use super::*;
+
+    #[test]
+    fn decodes_plain_utf8() {
+        let decoded = decode("olá\nmundo\n".as_bytes(), None);
+
+        assert_eq!(decoded.data, "olá\nmundo\n");
+        assert_eq!(decoded.encoding, "UTF-8");
+        assert!(!decoded.bom);
+        assert!(!decoded.lossy);
+        assert_eq!(decoded.line_ending, LineEnding::Lf);
+    }
+
+    #[test]
+    fn strips_and_reports_boms() {
+        let decoded = decode(b"\xEF\xBB\xBFhi", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.encoding, decoded.bom),
+            ("hi", "UTF-8", true)
+        );
+
+        let decoded = decode(b"\xFF\xFEh\0i\0", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.encoding, decoded.bom),
+            ("hi", "UTF-16LE", true)
+        );
+
+        let decoded = decode(b"\xFE\xFF\0h\0i", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.encoding, decoded.bom),
+            ("hi", "UTF-16BE", true)
+        );
+    }
+
+    #[test]
+    fn uses_the_meta_charset() {
+        let html = b"<html><head><meta charset=\"iso-8859-2\"></head><body>\xB1</body></html>";
+        let decoded = decode(html, None);
+
+        assert_eq!(decoded.encoding, ISO_8859_2.name());
+        assert!(decoded.data.contains('ą'));
+
+        let html = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">";
+        assert_eq!(decode(html, None).encoding, SHIFT_JIS.name());
+    }
+
+    #[test]
+    fn treats_utf16_meta_charsets_as_utf8() {
+        let decoded = decode("<meta charset=utf-16>é".as_bytes(), None);
+
+        assert_eq!(decoded.encoding, "UTF-8");
+        assert!(decoded.data.ends_with('é'));
+    }
+
+    #[test]
+    fn guesses_legacy_encodings() {
+        let decoded = decode(b"caf\xE9 cr\xE8me br\xFBl\xE9e, fa\xE7ade", None);
+
+        assert_eq!(decoded.encoding, "windows-1252");
+        assert_eq!(decoded.data, "café crème brûlée, façade");
+    }
+
+    #[test]
+    fn decodes_with_the_requested_encoding() {
+        let decoded = decode(b"caf\xE9", Some(encoding_for_label("latin1").unwrap()));
+        assert_eq!((decoded.data.as_str(), decoded.lossy), ("café", false));
+
+        let decoded = decode(b"caf\xE9", Some(UTF_8));
+        assert_eq!(
+            (decoded.data.as_str(), decoded.lossy),
+            ("caf\u{FFFD}", true)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_encoding_labels() {
+        assert!(encoding_for_label(" utf8 ").is_ok());
+        assert!(encoding_for_label("klingon").is_err());
+    }
+
+    #[test]
+    fn normalizes_line_endings_to_lf() {
+        let decoded = decode(b"a\r\nb\r\nc\n", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.line_ending),
+            ("a\nb\nc\n", LineEnding::Crlf)
+        );
+
+        let decoded = decode(b"a\rb\rc", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.line_ending),
+            ("a\nb\nc", LineEnding::Cr)
+        );
+
+        let decoded = decode(b"a\nb\r\nc\rd\n", None);
+        assert_eq!(
+            (decoded.data.as_str(), decoded.line_ending),
+            ("a\nb\nc\nd\n", LineEnding::Lf)
+        );
+    }
+
+    #[test]
+    fn encodes_line_endings_and_boms() {
+        assert_eq!(encode("a\nb", UTF_8, false, LineEnding::Crlf), b"a\r\nb");
+        assert_eq!(
+            encode("a\nb", UTF_8, true, LineEnding::Cr),
+            b"\xEF\xBB\xBFa\rb"
+        );
+        assert_eq!(encode("a\r\nb", UTF_8, false, LineEnding::Lf), b"a\nb");
+    }
+
+    #[test]
+    fn encodes_utf16() {
+        assert_eq!(
+            encode("hi", UTF_16LE, true, LineEnding::Lf),
+            b"\xFF\xFEh\0i\0"
+        );
+        assert_eq!(encode("hi", UTF_16BE, false, LineEnding::Lf), b"\0h\0i");
+    }
+
+    #[test]
+    fn escapes_characters_the_encoding_lacks() {
+        assert_eq!(
+            encode("café €ʒ", WINDOWS_1252, false, LineEnding::Lf),
+            b"caf\xE9 \x80&#658;"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_detected_format() {
+        let original = b"\xFF\xFEa\0\r\0\n\0\xE9\0";
+        let decoded = decode(original, None);
+        let encoding = Encoding::for_label(decoded.encoding.as_bytes()).unwrap();
+
+        assert_eq!(
+            encode(&decoded.data, encoding, decoded.bom, decoded.line_ending),
+            original
+        );
+    }
+
+    #[test]
+    fn round_trips_mixed_line_endings_to_the_most_common() {
+        let decoded = decode(b"a\r\nb\r\nc\nd\re\r\n", None);
+        let saved = encode(&decoded.data, UTF_8, decoded.bom, decoded.line_ending);
+
+        assert_eq!(saved, b"a\r\nb\r\nc\r\nd\r\ne\r\n");
+
+        // Saving again without edits changes nothing
+        let decoded = decode(&saved, None);
+        assert_eq!(
+            encode(&decoded.data, UTF_8, decoded.bom, decoded.line_ending),
+            saved
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use lenz_core::{
+    events::EventBus,
+    invoke::{InvokeError, InvokeErrorCode},
+};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use serde::Serialize;
+use serde_json::json;
+
+pub const CHANGE_EVENT: &str = "fs.change";
+
+// Quiet time before a batch of changes is reported, and the longest a busy
+// folder can delay it
+const DEBOUNCE: Duration = Duration::from_millis(100);
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+    Renamed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+}
+
+// Changes of one batch, merged per path in the order they first happened
+#[derive(Default)]
+struct Batch {
+    changes: Vec<Change>,
+    // Rename sources waiting for their destination, by rename cookie
+    moved_from: HashMap<usize, PathBuf>,
+}
+
+impl Batch {
+    fn position(&self, path: &Path) -> Option<usize> {
+        self.changes.iter().position(|change| change.path == path)
+    }
+
+    fn push(&mut self, kind: ChangeKind, path: PathBuf) {
+        let Some(index) = self.position(&path) else {
+            self.changes.push(Change {
+                kind,
+                path,
+                from: None,
+            });
+            return;
+        };
+
+        let previous = self.changes[index].kind;
+
+        match (previous, kind) {
+            // Never existed as far as the client knows
+            (ChangeKind::Created, ChangeKind::Removed) => {
+                self.changes.remove(index);
+            }
+            (ChangeKind::Created, _) => {}
+            (ChangeKind::Removed, ChangeKind::Created) => {
+                self.changes[index].kind = ChangeKind::Modified;
+            }
+            (_, kind) => {
+                self.changes[index].kind = kind;
+                self.changes[index].from = None;
+            }
+        }
+    }
+
+    fn rename(&mut self, from: PathBuf, to: PathBuf) {
+        match self.position(&from) {
+            // Saved through a temp file, like `fs.writeFile` does
+            Some(index) if self.changes[index].kind == ChangeKind::Created => {
+                self.changes.remove(index);
+                self.push(ChangeKind::Modified, to);
+            }
+            _ => {
+                if let Some(index) = self.position(&to) {
+                    self.changes.remove(index);
+                }
+
+                self.changes.push(Change {
+                    kind: ChangeKind::Renamed,
+                    path: to,
+                    from: Some(from),
+                });
+            }
+        }
+    }
+
+    fn add(&mut self, event: Event) {
+        let tracker = event.tracker();
+        let mut paths = event.paths.into_iter();
+
+        match event.kind {
+            EventKind::Create(_) => paths.for_each(|path| self.push(ChangeKind::Created, path)),
+            EventKind::Remove(_) => paths.for_each(|path| self.push(ChangeKind::Removed, path)),
+            // Paired renames come as `From`, `To` and then `Both`
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let Some(tracker) = tracker {
+                    self.moved_from.remove(&tracker);
+                }
+
+                if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                    self.rename(from, to);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => match tracker {
+                Some(tracker) => self.moved_from.extend(paths.map(|path| (tracker, path))),
+                None => paths.for_each(|path| self.push(ChangeKind::Removed, path)),
+            },
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                let paired = tracker.is_some_and(|tracker| self.moved_from.contains_key(&tracker));
+
+                if !paired {
+                    paths.for_each(|path| self.push(ChangeKind::Created, path));
+                }
+            }
+            // Unpaired renames, all that's left is checking what's there now
+            EventKind::Modify(ModifyKind::Name(_)) => paths.for_each(|path| {
+                let kind = if path.exists() {
+                    ChangeKind::Created
+                } else {
+                    ChangeKind::Removed
+                };
+
+                self.push(kind, path);
+            }),
+            EventKind::Modify(_) => paths.for_each(|path| self.push(ChangeKind::Modified, path)),
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => {}
+        }
+    }
+
+    // Moved somewhere outside of the watched folder
+    fn finish(mut self) -> Vec<Change> {
+        for (_, path) in std::mem::take(&mut self.moved_from) {
+            self.push(ChangeKind::Removed, path);
+        }
+
+        self.changes
+    }
+}
+
+// Runs until the watcher is dropped, which closes the channel. Only changes
+// to `file` are reported when watching a single file.
+fn debounce(
+    id: u64,
+    root: PathBuf,
+    file: Option<PathBuf>,
+    receiver: Receiver<notify::Result<Event>>,
+    events: EventBus,
+) {
+    let add = |batch: &mut Batch, event: notify::Result<Event>| match event {
+        Ok(event) => batch.add(event),
+        Err(e) => eprintln!("Watch error on {}: {}", root.display(), e),
+    };
+
+    while let Ok(event) = receiver.recv() {
+        let mut batch = Batch::default();
+        let started = Instant::now();
+
+        add(&mut batch, event);
+
+        loop {
+            let timeout = DEBOUNCE.min(MAX_DELAY.saturating_sub(started.elapsed()));
+
+            match receiver.recv_timeout(timeout) {
+                Ok(event) => add(&mut batch, event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        let mut changes = batch.finish();
+
+        if let Some(file) = &file {
+            changes.retain(|change| &change.path == file || change.from.as_ref() == Some(file));
+        }
+
+        if !changes.is_empty() {
+            events.emit(
+                CHANGE_EVENT,
+                json!({ "id": id, "path": root, "changes": changes }),
+            );
+        }
+    }
+}
+
+// Dropping a watcher stops it, along with its debounce thread
+struct Watch {
+    // The channel connection that started it, the only one getting its
+    // changes
+    connection: u64,
+    watcher: RecommendedWatcher,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct Watches {
+    next_id: AtomicU64,
+    watchers: Mutex<HashMap<u64, Watch>>,
+    // Debounce threads of stopped watches that may still be running
+    stopping: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Watches {
+    pub fn watch(
+        &self,
+        connection: u64,
+        path: PathBuf,
+        recursive: bool,
+        events: EventBus,
+    ) -> Result<u64, InvokeError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (sender, receiver) = mpsc::channel();
+
+        // Symlinks may point outside of the scopes
+        let config = Config::default().with_follow_symlinks(false);
+        let mut watcher = RecommendedWatcher::new(sender, config).map_err(watch_error)?;
+
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+
+        // Files are watched through their folder, saves that replace the
+        // file (like `fs.writeFile`) would end a watch on the file itself
+        let file = match path.parent() {
+            Some(parent) if !path.is_dir() => {
+                watcher
+                    .watch(parent, RecursiveMode::NonRecursive)
+                    .map_err(watch_error)?;
+                Some(path.clone())
+            }
+            _ => {
+                watcher.watch(&path, mode).map_err(watch_error)?;
+                None
+            }
+        };
+
+        let thread = std::thread::Builder::new()
+            .name(format!("fs-watch-{}", id))
+            .spawn(move || debounce(id, path, file, receiver, events))?;
+
+        self.watchers.lock().unwrap().insert(
+            id,
+            Watch {
+                connection,
+                watcher,
+                thread,
+            },
+        );
+
+        Ok(id)
+    }
+
+    // Watches of other connections are reported as missing too
+    pub fn unwatch(&self, connection: u64, id: u64) -> Result<(), InvokeError> {
+        let watch = {
+            let mut watchers = self.watchers.lock().unwrap();
+
+            match watchers.get(&id) {
+                Some(watch) if watch.connection == connection => watchers.remove(&id),
+                _ => None,
+            }
+        };
+
+        match watch {
+            Some(watch) => {
+                self.stop([watch]);
+                Ok(())
+            }
+            None => Err(InvokeError::not_found(format!("No watch with id {}", id))
+                .with_details(json!({ "id": id }))),
+        }
+    }
+
+    // Nobody is left to receive the changes
+    pub fn disconnect(&self, connection: u64) {
+        let watches = {
+            let mut watchers = self.watchers.lock().unwrap();
+            let ids = watchers
+                .iter()
+                .filter(|(_, watch)| watch.connection == connection)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+
+            ids.into_iter()
+                .filter_map(|id| watchers.remove(&id))
+                .collect::<Vec<_>>()
+        };
+
+        self.stop(watches);
+    }
+
+    // The debounce threads end on their own once their watcher is dropped,
+    // they are kept around until then for `clear`
+    fn stop(&self, watches: impl IntoIterator<Item = Watch>) {
+        let mut stopping = self.stopping.lock().unwrap();
+        stopping.retain(|thread| !thread.is_finished());
+
+        for watch in watches {
+            drop(watch.watcher);
+            stopping.push(watch.thread);
+        }
+    }
+
+    // Waits for the debounce threads, they run code from this library which
+    // may be unloaded right after
+    pub fn clear(&self) {
+        let watchers = std::mem::take(&mut *self.watchers.lock().unwrap());
+        self.stop(watchers.into_values());
+
+        let threads = std::mem::take(&mut *self.stopping.lock().unwrap());
+
+        for thread in threads {
+            thread.join().ok();
+        }
+    }
+}
+
+fn watch_error(e: notify::Error) -> InvokeError {
+    let message = e.to_string();
+
+    match e.kind {
+        notify::ErrorKind::Io(io) => InvokeError::from(io),
+        notify::ErrorKind::PathNotFound => {
+            InvokeError::not_found(message).with_details(json!({ "path": e.paths.first() }))
+        }
+        // inotify limits, see `fs.inotify.max_user_watches`
+        notify::ErrorKind::MaxFilesWatch => InvokeError::new(InvokeErrorCode::Unavailable, message),
+        _ => InvokeError::internal(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lenz_core::invoke::InvokeErrorCode;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn count(watches: &Watches) -> usize {
+        watches.watchers.lock().unwrap().len()
+    }
+
+    #[test]
+    fn only_unwatches_own_watches() {
+        let dir = TempDir::new();
+        let watches = Watches::default();
+        let id = watches
+            .watch(1, dir.path().to_path_buf(), false, EventBus::new())
+            .unwrap();
+
+        assert_eq!(
+            watches.unwatch(2, id).unwrap_err().code,
+            InvokeErrorCode::NotFound
+        );
+        assert_eq!(count(&watches), 1);
+
+        watches.unwatch(1, id).unwrap();
+
+        assert_eq!(count(&watches), 0);
+    }
+
+    #[test]
+    fn drops_watches_of_closed_connections() {
+        let dir = TempDir::new();
+        let watches = Watches::default();
+        let path = dir.path().to_path_buf();
+
+        watches
+            .watch(1, path.clone(), false, EventBus::new())
+            .unwrap();
+        watches
+            .watch(1, path.clone(), true, EventBus::new())
+            .unwrap();
+        let other = watches.watch(2, path, false, EventBus::new()).unwrap();
+
+        watches.disconnect(1);
+
+        assert_eq!(count(&watches), 1);
+        assert!(watches.watchers.lock().unwrap().contains_key(&other));
+    }
+
+    #[test]
+    fn clear_waits_for_stopped_watches() {
+        let dir = TempDir::new();
+        let watches = Watches::default();
+        let id = watches
+            .watch(1, dir.path().to_path_buf(), false, EventBus::new())
+            .unwrap();
+
+        watches.unwatch(1, id).unwrap();
+        watches.clear();
+
+        assert!(watches.stopping.lock().unwrap().is_empty());
+    }
+}
@@ -1,6 +1,14 @@
 declare module 'lenz:invoke' {
     type FormValue = string | number | boolean | null | File | FileList | Blob
     type InvokeArgs = Record<string, FormValue | Array<FormValue>>
+
+    /**
+     * Erro de execução de comando, com o código retornado pelo agente.
+     */
+    export class InvokeError extends Error {
+        readonly code: string;
+        readonly details?: unknown;
+    }
     
     /**
      * Executa um comando no servidor.
@@ -27,6 +35,17 @@ declare module 'lenz:invoke' {
      */
     export function invoke<T>(command: string, args?: InvokeArgs): Promise<T>;
 
+    /**
+     * Executa um comando no servidor, retornando também os metadados do resultado.
+     * @param command Comando a ser executado.
+     * @param args Argumentos do comando.
+     * @returns Promise com o resultado e os metadados, como a versão de um arquivo lido.
+     */
+    export function invokeWithMeta<T>(
+        command: string,
+        args?: InvokeArgs
+    ): Promise<{ result: T; meta: Record<string, unknown> }>;
+
     /**
      * Executa um comando no servidor de forma síncrona.
      * @param command Comando a ser executado.
@@ -1,5 +1,7 @@
 import { defineStore } from "pinia";
 
+import { on } from "lenz:channel";
+
 export const useExtensionsStore = defineStore("extensions", () => {
   const extensionsToLoad = window.__LENZ_EXTENSIONS__ || [];
   delete window.__LENZ_EXTENSIONS__;
@@ -11,6 +13,17 @@ export const useExtensionsStore = defineStore("extensions", () => {
   }>();
 
   async function loadExtension(extension: any) {
+    // O agente não conseguiu ativar a extensão, seus comandos não existem
+    if (extension.status === "failed") {
+      console.warn(`Extensão ${extension.id} não foi carregada: ${extension.error}`);
+      return;
+    }
+
+    // Desativada pelo usuário
+    if (extension.status !== "active") {
+      return;
+    }
+
     try {
       loadingState.value = {
         extension,
@@ -64,6 +77,23 @@ export const useExtensionsStore = defineStore("extensions", () => {
     }
   }
 
+  // Extensões ativadas, desativadas ou recarregadas no agente durante a sessão
+  on("extensions.changed", async ({ extension }: { extension: any }) => {
+    if (loadedExtensions.value.has(extension.id)) {
+      await unloadExtension(extension);
+    }
+
+    await loadExtension(extension);
+  });
+
+  // Modo de desenvolvimento (LENZ_DEV): arquivos da extensão mudaram no disco.
+  // O import map e os módulos já importados não podem ser trocados, então a
+  // página é recarregada inteira
+  on("extensions.reloaded", ({ id }: { id: string }) => {
+    console.info(`Extensão ${id} recarregada, recarregando a página`);
+    location.reload();
+  });
+
   async function init() {
     for (const extension of extensionsToLoad) {
       if (!loadedExtensions.value.has(extension.id)) {
@@ -2,8 +2,10 @@ import { defineStore } from "pinia";
 
 import * as fs from "lenz:fs";
 
-import { invoke } from "lenz:invoke";
+import { on } from "lenz:channel";
+import { invoke, InvokeError } from "lenz:invoke";
 import { isEqual } from "lodash-es";
+import { useDialogStore } from "./dialog";
 import { useHistoryStore } from "./history";
 
 import iconQuit from "lenz:icons/close_circle";
@@ -17,18 +19,33 @@ import {
   saveFile as openSaveDialog,
 } from "lenz:file-dialog";
 
+// Como o arquivo está gravado no disco, preservado ao salvar
+export interface TextFormat {
+  encoding: string;
+  bom: boolean;
+  lineEnding: "lf" | "crlf" | "cr";
+}
+
 export class EditorFile {
   dirty = false;
 
   constructor(
     public filepath: string,
-    public data: string
+    public data: string,
+    // Versão do arquivo no disco quando foi lido ou salvo pela última vez
+    public version?: string,
+    public format?: TextFormat
   ) {}
 
   static async open(filepath: string) {
-    const data = await fs.readFile(filepath);
+    const { data, version, encoding, bom, lineEnding } =
+      await fs.readTextFile(filepath);
 
-    return new EditorFile(filepath, new TextDecoder().decode(data));
+    return new EditorFile(filepath, data, version, {
+      encoding,
+      bom,
+      lineEnding,
+    });
   }
 
   text() {
@@ -49,7 +66,37 @@ export class EditorFile {
   }
 
   async save(data = this.data) {
-    await fs.writeFile(this.filepath, data);
+    try {
+      const { version } = await fs.writeTextFile(this.filepath, data, {
+        ...this.format,
+        expectedVersion: this.version,
+      });
+      this.version = version;
+    } catch (e) {
+      if (!(e instanceof InvokeError) || e.code !== "Conflict") {
+        throw e;
+      }
+
+      // Outro programa alterou o arquivo depois que ele foi aberto
+      const overwrite = await useDialogStore()
+        .confirm({
+          title: "Arquivo modificado",
+          message: `${this.filepath} foi alterado fora do editor. Deseja sobrescrever?`,
+        })
+        .catch(() => false);
+
+      if (!overwrite) {
+        return;
+      }
+
+      const { version } = await fs.writeTextFile(
+        this.filepath,
+        data,
+        this.format
+      );
+      this.version = version;
+    }
+
     this.dirty = false;
   }
 }
@@ -63,6 +110,7 @@ export const useFileStore = defineStore("file", () => {
   const hooksStore = useHooksStore();
 
   const autoSaveTimers = new Map<string, number>();
+  const fileWatchers = new Map<string, Promise<() => void>>();
 
   const openedFiles = ref<Map<string, EditorFile>>(new Map());
   const recents = useLocalStorage<string[]>("lenz.file.recents", []);
@@ -95,6 +143,33 @@ export const useFileStore = defineStore("file", () => {
     );
   }
 
+  // Recarrega o arquivo quando alterado fora do editor, se não houver
+  // alterações pendentes
+  function watchFile(filepath: string) {
+    const watcher = fs.watch(filepath, async (changes: { kind: string }[]) => {
+      const file = openedFiles.value.get(filepath);
+
+      if (!file || file.dirty || changes.every(({ kind }) => kind === "removed")) {
+        return;
+      }
+
+      const { data, version, encoding, bom, lineEnding } =
+        await fs.readTextFile(filepath);
+
+      if (version === file.version) {
+        return;
+      }
+
+      file.data = data;
+      file.version = version;
+      file.format = { encoding, bom, lineEnding };
+      historyStore.save(filepath, { data: data.slice(), selection: [] });
+    });
+
+    watcher.catch((e: unknown) => console.warn("fs.watch", filepath, e));
+    fileWatchers.set(filepath, watcher);
+  }
+
   async function openFile(filepath: string) {
     if (openedFiles.value.has(filepath)) {
       return openedFiles.value.get(filepath);
@@ -110,6 +185,7 @@ export const useFileStore = defineStore("file", () => {
     });
 
     openedFiles.value.set(filepath, file);
+    watchFile(filepath);
     currentFilename.value = filepath;
     recents.value = [
       filepath,
@@ -120,6 +196,11 @@ export const useFileStore = defineStore("file", () => {
   }
 
   function closeFile(filepath: string) {
+    fileWatchers
+      .get(filepath)
+      ?.then((unwatch) => unwatch())
+      .catch(() => {});
+    fileWatchers.delete(filepath);
     openedFiles.value.delete(filepath);
     historyStore.drop(filepath);
   }
@@ -178,6 +259,19 @@ export const useFileStore = defineStore("file", () => {
     openFile(currentFilename.value);
   }
 
+  const initialFile = new URLSearchParams(location.search).get("file");
+
+  if (initialFile) {
+    openFile(initialFile);
+  }
+
+  // Arquivos repassados por uma segunda instância do agente
+  on("app.open", ({ file }: { file: string | null }) => {
+    if (file) {
+      openFile(file);
+    }
+  });
+
   if (import.meta.env.PROD) {
     window.addEventListener("beforeunload", async () => {
       await commandsStore.executeCommand("app.quit");
@@ -237,11 +331,13 @@ export const useFileStore = defineStore("file", () => {
       icon: iconFile,
       description: "Abrir uma página HTML",
       async run() {
+        // O arquivo é editado e salvo, então pede acesso de escrita
         const filepath = await openFileDialog({
           title: "Abrir arquivo HTML",
           filters: {
             "Páginas HTML": ["*.html"],
           },
+          access: "write",
         });
         await openFile(filepath);
       },
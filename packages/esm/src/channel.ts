@@ -0,0 +1,283 @@
+/**
+ * Canal WebSocket bidirecional com o agente
+ * @module lenz:channel
+ */
+
+import { BASE_URL, InvokeError, authToken, type InvokeOptions } from "./invoke.js";
+
+type EventListener = (data: any) => void;
+
+interface PendingCall {
+  resolve: (value: any) => void;
+  reject: (reason: any) => void;
+}
+
+interface StreamController {
+  push: (chunk: any) => void;
+  end: (error?: string | null) => void;
+}
+
+const listeners = new Map<string, Set<EventListener>>();
+const pending = new Map<number, PendingCall>();
+const streams = new Map<number, StreamController>();
+
+let socket: Promise<WebSocket> | null = null;
+let nextId = 1;
+let awaitingBinary: PendingCall | null = null;
+
+function dispatch(event: string, data: any) {
+  for (const listener of listeners.get(event) ?? []) {
+    listener(data);
+  }
+
+  for (const listener of listeners.get("*") ?? []) {
+    listener({ event, data });
+  }
+}
+
+function createStream(id: number): AsyncIterable<any> {
+  const queue: any[] = [];
+  let done = false;
+  let failure: Error | null = null;
+  let wake: (() => void) | null = null;
+
+  const notify = () => {
+    wake?.();
+    wake = null;
+  };
+
+  streams.set(id, {
+    push(chunk) {
+      queue.push(chunk);
+      notify();
+    },
+    end(error) {
+      done = true;
+      failure = error ? new InvokeError(error) : null;
+      streams.delete(id);
+      notify();
+    },
+  });
+
+  return {
+    async *[Symbol.asyncIterator]() {
+      while (true) {
+        if (queue.length > 0) {
+          yield queue.shift();
+          continue;
+        }
+
+        if (failure) {
+          throw failure;
+        }
+
+        if (done) {
+          return;
+        }
+
+        await new Promise<void>((resolve) => (wake = resolve));
+      }
+    },
+  };
+}
+
+function handleMessage(message: MessageEvent) {
+  if (message.data instanceof ArrayBuffer) {
+    awaitingBinary?.resolve(message.data);
+    awaitingBinary = null;
+    return;
+  }
+
+  const payload = JSON.parse(message.data);
+
+  if (payload.type === "chunk") {
+    const stream = streams.get(payload.id);
+
+    if (payload.data === null) {
+      awaitingBinary = { resolve: (chunk) => stream?.push(chunk), reject: () => {} };
+    } else {
+      stream?.push(payload.data);
+    }
+
+    return;
+  }
+
+  if (payload.type === "end") {
+    streams.get(payload.id)?.end(payload.error);
+    return;
+  }
+
+  if (payload.type === "event") {
+    dispatch(payload.event, payload.data);
+    return;
+  }
+
+  if (payload.type === "result") {
+    const call = pending.get(payload.id);
+
+    if (!call) {
+      return;
+    }
+
+    pending.delete(payload.id);
+
+    switch (payload.result) {
+      case "error":
+        call.reject(InvokeError.from(payload.data));
+        break;
+      case "binary":
+        awaitingBinary = call;
+        break;
+      case "stream":
+        call.resolve(createStream(payload.id));
+        break;
+      case "void":
+        call.resolve(undefined);
+        break;
+      default:
+        call.resolve(payload.data);
+    }
+  }
+}
+
+/**
+ * Abre (ou reutiliza) a conexão com o agente
+ * @returns Promise com o WebSocket conectado
+ */
+export function connect(): Promise<WebSocket> {
+  if (socket) {
+    return socket;
+  }
+
+  socket = new Promise((resolve, reject) => {
+    const url = `${BASE_URL.replace(/^http/, "ws")}/lenz-channel?token=${authToken()}`;
+    const ws = new WebSocket(url);
+
+    ws.binaryType = "arraybuffer";
+
+    ws.addEventListener("open", () => resolve(ws));
+    ws.addEventListener("message", handleMessage);
+    ws.addEventListener("error", (error) => reject(error));
+    ws.addEventListener("close", () => {
+      socket = null;
+
+      for (const call of pending.values()) {
+        call.reject(new InvokeError("Conexão com o agente encerrada", "Unavailable"));
+      }
+
+      pending.clear();
+
+      for (const stream of streams.values()) {
+        stream.end("Conexão com o agente encerrada");
+      }
+    });
+  });
+
+  return socket;
+}
+
+/**
+ * Escuta um evento emitido pelo agente ou por extensões.
+ * Use `*` para receber todos os eventos.
+ * @param event Nome do evento
+ * @param listener Função a ser executada
+ * @returns Disposer
+ */
+export function on(event: string, listener: EventListener) {
+  if (!listeners.has(event)) {
+    listeners.set(event, new Set());
+  }
+
+  listeners.get(event)!.add(listener);
+
+  connect();
+
+  return () => {
+    listeners.get(event)?.delete(listener);
+  };
+}
+
+/**
+ * Invoca um comando no agente através do canal
+ * @param command Comando a ser invocado
+ * @param args Argumentos do comando
+ * @param options Opções da invocação
+ * @returns Promise com o resultado da execução
+ */
+export async function call<T>(
+  command: string,
+  args: Record<string, unknown> = {},
+  options: InvokeOptions = {}
+): Promise<T> {
+  const { signal } = options;
+
+  signal?.throwIfAborted();
+
+  const ws = await connect();
+  const id = nextId++;
+
+  return new Promise<T>((resolve, reject) => {
+    pending.set(id, { resolve, reject });
+
+    // Streams em andamento também são cancelados, encerrando com erro
+    signal?.addEventListener(
+      "abort",
+      () => {
+        ws.send(JSON.stringify({ type: "cancel", id }));
+
+        if (pending.delete(id)) {
+          reject(new InvokeError("Requisição cancelada", "Cancelled"));
+        }
+      },
+      { once: true }
+    );
+
+    ws.send(JSON.stringify({ type: "invoke", id, command, args }));
+  });
+}
+
+/**
+ * Emite um evento para todos os clientes conectados ao agente.
+ * Clientes só emitem eventos `client.*`, os demais são do agente e das extensões.
+ * @param event Nome do evento, como `client.minhaExtensao.salvo`
+ * @param data Dados do evento
+ */
+export function emit(event: string, data?: unknown) {
+  return call<void>("app.emit", { event, data });
+}
+
+/**
+ * Pede ao usuário que permita um comando que exige confirmação, como `fs.grant`.
+ * A confirmação acontece numa página do próprio agente, fora do alcance da
+ * página que pediu.
+ * @param command Comando a ser confirmado
+ * @param args Argumentos do comando
+ * @returns Promise com o resultado do comando, ou `undefined` se o usuário negar
+ */
+export async function confirmCall<T>(
+  command: string,
+  args: Record<string, unknown> = {}
+): Promise<T | undefined> {
+  const { id, url } = await call<{ id: string; url: string }>("app.confirm", { command, args });
+
+  const answer = new Promise<any>((resolve) => {
+    const dispose = on("app.confirmed", (data) => {
+      if (data.id === id) {
+        dispose();
+        resolve(data);
+      }
+    });
+  });
+
+  if (!window.open(`${BASE_URL}${url}`, "_blank", "popup,width=520,height=420")) {
+    throw new InvokeError("O navegador bloqueou a janela de confirmação", "Unavailable");
+  }
+
+  const { allowed, result, error } = await answer;
+
+  if (error) {
+    throw InvokeError.from(error);
+  }
+
+  return allowed ? result : undefined;
+}
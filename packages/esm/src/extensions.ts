@@ -2,6 +2,7 @@
  * @module lenz:extensions 
  */
 
+import { invoke } from "./invoke.js";
 import { ensureInitialized } from "./util.js";
 
 /**
@@ -12,3 +13,43 @@ import { ensureInitialized } from "./util.js";
 export function getExtension(id: string) {
   return ensureInitialized().extensions().getExtension(id);
 }
+
+/**
+ * Situação de uma extensão no agente
+ */
+export type ExtensionStatus = "active" | "failed" | "disabled" | "inactive";
+
+/**
+ * Lista as extensões instaladas, inclusive as desativadas
+ * @returns
+ */
+export function listExtensions() {
+  return invoke<any[]>("extensions.list");
+}
+
+/**
+ * Ativa uma extensão e a mantém ativada nas próximas sessões
+ * @param id
+ * @returns
+ */
+export function enableExtension(id: string) {
+  return invoke<any>("extensions.enable", { id });
+}
+
+/**
+ * Desativa uma extensão e a mantém desativada nas próximas sessões
+ * @param id
+ * @returns
+ */
+export function disableExtension(id: string) {
+  return invoke<any>("extensions.disable", { id });
+}
+
+/**
+ * Desativa e ativa novamente uma extensão a partir do disco
+ * @param id
+ * @returns
+ */
+export function reloadExtension(id: string) {
+  return invoke<any>("extensions.reload", { id });
+}
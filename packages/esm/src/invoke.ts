@@ -3,6 +3,24 @@
  * @module lenz:invoke 
  */
 
+/**
+ * URL base do agente, definida pelo `lenz-init.js`
+ */
+export const BASE_URL: string = (globalThis as any).__LENZ_BASE_URL__ ?? "http://localhost:5369";
+
+/**
+ * Token da sessão do agente, definido nas páginas servidas pelo agente ou
+ * pelo `lenz-init.js` carregado de uma origem permitida.
+ * Webviews sem o script usam o token da janela principal.
+ */
+export function authToken(): string {
+  try {
+    return (globalThis as any).__LENZ_TOKEN__ ?? (window.parent as any).__LENZ_TOKEN__ ?? "";
+  } catch {
+    return "";
+  }
+}
+
 /**
  * Cria uma requisição Invoke para o servidor
  * @param command
@@ -21,9 +39,9 @@ function createRequest(command: string, args: Record<string, unknown> = {}) {
   }
 
   return {
-    url: `http://localhost:5369/${command}`,
+    url: `${BASE_URL}/${command}`,
     method: "POST",
-    headers: new Headers(),
+    headers: new Headers({ "X-Lenz-Token": authToken() }),
     body: form,
   };
 }
@@ -49,14 +67,42 @@ function isContentTypeBinary(contentType: any): contentType is "application/octe
   return typeof contentType === 'string' && contentType.includes("application/octet-stream");
 }
 
+/**
+ * Código de erro retornado pelo agente
+ */
+export type InvokeErrorCode =
+  | "InvalidArgument"
+  | "NotFound"
+  | "AlreadyExists"
+  | "PermissionDenied"
+  | "Unauthorized"
+  | "Conflict"
+  | "Cancelled"
+  | "Timeout"
+  | "RangeNotSatisfiable"
+  | "Unsupported"
+  | "Unavailable"
+  | "Internal";
+
 /**
  * Erro de execução de comando
  */
 export class InvokeError extends Error {
-  constructor(message: string) {
+  constructor(
+    message: string,
+    public readonly code: InvokeErrorCode = "Internal",
+    public readonly details?: unknown,
+  ) {
     super(message);
     this.name = "InvokeError";
   }
+
+  /**
+   * Cria um erro a partir do corpo de erro retornado pelo agente
+   */
+  static from(error: any) {
+    return new InvokeError(error?.message ?? String(error), error?.code, error?.details);
+  }
 }
 
 interface ParseOptions {
@@ -74,6 +120,9 @@ interface ParseOptions {
 
   /** Função que retorna o binário da resposta */
   binary?: () => Promise<ArrayBuffer> | ArrayBuffer;
+
+  /** Função que retorna o stream da resposta */
+  stream?: () => ReadableStream<Uint8Array> | null;
 }
 
 /**
@@ -87,6 +136,7 @@ function parseResponse({
   text = () => "",
   json = () => null,
   binary = () => new ArrayBuffer(0),
+  stream = () => null,
 }: ParseOptions = {}) {
   if (resultType === "void") {
     return;
@@ -96,6 +146,16 @@ function parseResponse({
     return text();
   }
 
+  if (resultType === "error" && isContentTypeJson(contentType)) {
+    const result = json();
+
+    if (result instanceof Promise) {
+      return result.then((error) => Promise.reject(InvokeError.from(error)));
+    }
+
+    throw InvokeError.from(result);
+  }
+
   if (resultType === "error" && isContentTypePlainText(contentType)) {
     const result = text();
 
@@ -114,35 +174,74 @@ function parseResponse({
     return json();
   }
 
+  if (resultType === "stream") {
+    return stream();
+  }
+
   console.error("Invalid response", {
     resultType,
     contentType,
   });
 }
 
+/**
+ * Opções de uma invocação
+ */
+export interface InvokeOptions {
+  /** Cancela a execução do comando no agente quando abortado */
+  signal?: AbortSignal;
+}
+
 /**
  * Invoca um comando no servidor de forma assíncrona
  * @param command Comando a ser invocado
  * @param args Argumentos do comando
+ * @param options Opções da invocação
  * @returns Promise com o resultado da execução
  */
-export async function invoke<T>(command: string, args: Record<string, unknown> = {}): Promise<T> {
+export async function invoke<T>(
+  command: string,
+  args: Record<string, unknown> = {},
+  options: InvokeOptions = {}
+): Promise<T> {
+  const { result } = await invokeWithMeta<T>(command, args, options);
+
+  return result;
+}
+
+/**
+ * Invoca um comando no servidor, retornando também os metadados do resultado,
+ * como a versão de um arquivo lido
+ * @param command Comando a ser invocado
+ * @param args Argumentos do comando
+ * @param options Opções da invocação
+ * @returns Promise com o resultado e os metadados
+ */
+export async function invokeWithMeta<T>(
+  command: string,
+  args: Record<string, unknown> = {},
+  options: InvokeOptions = {}
+): Promise<{ result: T; meta: Record<string, unknown> }> {
   const { body, headers, method, url } = createRequest(command, args);
 
-  return fetch(url, {
+  const response = await fetch(url, {
     method,
     headers,
     body,
     keepalive: true,
-  }).then((response) =>
-    parseResponse({
-      contentType: response.headers.get("Content-Type") ?? '',
-      resultType: response.headers.get("X-Invoke-Result") ?? '',
-      text: () => response.text(),
-      json: () => response.json(),
-      binary: () => response.arrayBuffer(),
-    }) as T
-  );
+    signal: options.signal,
+  });
+
+  const result = await (parseResponse({
+    contentType: response.headers.get("Content-Type") ?? '',
+    resultType: response.headers.get("X-Invoke-Result") ?? '',
+    text: () => response.text(),
+    json: () => response.json(),
+    binary: () => response.arrayBuffer(),
+    stream: () => response.body,
+  }) as T);
+
+  return { result, meta: JSON.parse(response.headers.get("X-Invoke-Meta") ?? "{}") };
 }
 
 /**
@@ -30,10 +30,13 @@ function waitForPort(port) {
   });
 }
 
-function startLenz(executable) {
+function startLenz(executable, allowedOrigins) {
   return new Promise((resolve, reject) => {
     console.log("Inicializando o Lenz...", executable);
-    const lenz = spawn(executable, ["--no-browser"]);
+    // O agente só aceita requisições de origens conhecidas
+    const lenz = spawn(executable, ["--no-browser"], {
+      env: { ...process.env, LENZ_ALLOWED_ORIGINS: allowedOrigins.join(",") },
+    });
 
     lenz.addListener("error", reject);
     lenz.addListener("exit", (code) => {
@@ -46,7 +49,7 @@ function startLenz(executable) {
   });
 }
 
-async function ensureLenz(executable) {
+async function ensureLenz(executable, allowedOrigins) {
   let retries = 3;
 
   do {
@@ -59,7 +62,7 @@ async function ensureLenz(executable) {
       return new Set(imports);
     }
 
-    await startLenz(executable);
+    await startLenz(executable, allowedOrigins);
   } while (retries-- > 0);
 }
 
@@ -109,7 +112,12 @@ export function Lenz({
 
       if (!isPluginAdded) {
         if (mode !== "production") {
-          await ensureLenz(lenzExecutable);
+          const port = config.server?.port ?? 5173;
+
+          await ensureLenz(lenzExecutable, [
+            `http://localhost:${port}`,
+            `http://127.0.0.1:${port}`,
+          ]);
         }
         const originalExternal = config.build.rollupOptions.external ?? [];
 
@@ -196,6 +204,8 @@ export function Lenz({
             injectTo: "head-prepend",
             attrs: {
               src: "http://localhost:5369/lenz-init.js",
+              // Envia o Origin, o agente só inclui o token para origens permitidas
+              crossorigin: "anonymous",
             },
           },
         ],